@@ -0,0 +1,80 @@
+use crate::cpu::{CPULockup, CPU};
+use crate::test_rom;
+use std::time::Instant;
+
+// A loop of loads, arithmetic, (HL) accesses, prefixed instructions, stack operations and jumps, started at 0x0100
+//
+// 0100: LD HL, 0xC000
+// 0103: LD B, 0x10
+// 0105: LD A, (HL)
+// 0106: ADD A, B
+// 0107: LD (HL+), A
+// 0108: XOR C
+// 0109: SWAP A
+// 010B: BIT 0, (HL)
+// 010D: PUSH BC
+// 010E: POP DE
+// 010F: INC DE
+// 0110: DEC B
+// 0111: JR NZ, 0x0105
+// 0113: JP 0x0100
+const PROGRAM: [u8; 22] = [
+    0x21, 0x00, 0xC0, 0x06, 0x10, 0x7E, 0x80, 0x22, 0xA9, 0xCB, 0x37, 0xCB, 0x46, 0xC5, 0xD1, 0x13,
+    0x05, 0x20, 0xF2, 0xC3, 0x00, 0x01,
+];
+
+struct BenchResult {
+    instructions: u64,
+    seconds: f64,
+}
+
+impl BenchResult {
+    fn mips(&self) -> f64 {
+        self.instructions as f64 / self.seconds / 1_000_000.0
+    }
+}
+
+/// Executes the benchmark program for `million_cycles` million T-cycles with both the dispatch table and the decoded
+/// instruction match, and prints the number of instructions executed per second.
+///
+/// Only meaningful for release builds: `cargo run --release -- --bench`
+pub fn run(million_cycles: u64) {
+    let cycles = million_cycles * 1_000_000;
+
+    let table = measure(cycles, CPU::step);
+    let decoded = measure(cycles, CPU::step_decoded);
+
+    println!("Executed {} million T-cycles", million_cycles);
+    println!(
+        "Dispatch table:  {:>10} instructions in {:.3}s, {:.2} MIPS",
+        table.instructions,
+        table.seconds,
+        table.mips()
+    );
+    println!(
+        "Decoded match:   {:>10} instructions in {:.3}s, {:.2} MIPS",
+        decoded.instructions,
+        decoded.seconds,
+        decoded.mips()
+    );
+    println!("Speedup: {:.2}x", table.mips() / decoded.mips());
+}
+
+fn measure(cycles: u64, step: fn(&mut CPU) -> Result<u8, CPULockup>) -> BenchResult {
+    let mut cpu = test_rom::from_rom(test_rom::rom_with_program(&PROGRAM))
+        .expect("The benchmark rom is supported");
+
+    let mut elapsed_cycles = 0;
+    let mut instructions = 0;
+    let start = Instant::now();
+
+    while elapsed_cycles < cycles {
+        elapsed_cycles += step(&mut cpu).expect("The benchmark program never locks up") as u64;
+        instructions += 1;
+    }
+
+    BenchResult {
+        instructions,
+        seconds: start.elapsed().as_secs_f64(),
+    }
+}
@@ -0,0 +1,76 @@
+/// Everything the CPU is connected to: the memory map and the hardware that runs alongside the CPU.
+pub trait Bus {
+    /// Reads the byte at `address` without advancing time.
+    fn read(&self, address: u16) -> u8;
+
+    /// Writes `value` to `address` without advancing time.
+    fn write(&mut self, address: u16, value: u8);
+
+    /// Advances the hardware that runs alongside the CPU by one M-cycle (4 T-cycles).
+    fn tick(&mut self);
+
+    /// The rom bank mapped at `address`, or 0 if it isn't in rom.
+    fn rom_bank(&self, _address: u16) -> u8 {
+        0
+    }
+
+    /// Called when STOP is executed. Returns true if STOP switched the CPU speed instead of stopping the CPU.
+    fn switch_speed(&mut self) -> bool {
+        false
+    }
+}
+
+/// 64 KiB of RAM without a memory map, IO registers or any hardware ticking along with the CPU.
+///
+/// Every address can be read and written, which makes it easy to put the CPU in any state in tests.
+#[cfg(test)]
+pub struct FlatRam {
+    pub bytes: [u8; 0x10000],
+}
+
+#[cfg(test)]
+impl FlatRam {
+    pub fn new() -> Self {
+        FlatRam {
+            bytes: [0; 0x10000],
+        }
+    }
+
+    /// Copies `data` into RAM starting at `address`.
+    pub fn load(&mut self, address: u16, data: &[u8]) {
+        let start = address as usize;
+        self.bytes[start..start + data.len()].copy_from_slice(data);
+    }
+}
+
+#[cfg(test)]
+impl Bus for FlatRam {
+    fn read(&self, address: u16) -> u8 {
+        self.bytes[address as usize]
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.bytes[address as usize] = value;
+    }
+
+    fn tick(&mut self) {}
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    #[test]
+    fn flat_ram_reads_back_every_address() {
+        let mut ram = FlatRam::new();
+        ram.load(0xFFFE, &[0x12, 0x34]);
+        ram.write(0x0000, 0x56);
+        ram.write(0xFF04, 0x78);
+        ram.tick();
+
+        assert_eq!(ram.read(0xFFFE), 0x12);
+        assert_eq!(ram.read(0xFFFF), 0x34);
+        assert_eq!(ram.read(0x0000), 0x56);
+        assert_eq!(ram.read(0xFF04), 0x78);
+    }
+}
@@ -0,0 +1,396 @@
+use super::instructions::*;
+use super::operands::{IncDecOperand, Location16, Location8, Operand16, Operand8, Register16};
+use crate::bus::Bus;
+use std::fmt;
+
+/// An instruction read from memory along with where it was found and the bytes it is encoded in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Disassembly {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub instruction: Instruction,
+    // The instruction in RGBDS syntax, with the values of its immediate operands
+    pub text: String,
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes: Vec<String> = self
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+
+        write!(
+            f,
+            "{:04X}: {:<8}  {}",
+            self.address,
+            bytes.join(" "),
+            self.text
+        )
+    }
+}
+
+/// Disassembles `count` instructions, starting with the one at `start`.
+///
+/// Reading memory this way has no side effects, no time passes.
+pub fn disassemble<B: Bus>(
+    memory: &B,
+    start: u16,
+    count: usize,
+) -> impl Iterator<Item = Disassembly> + '_ {
+    let mut address = start;
+
+    (0..count).map(move |_| {
+        let disassembly = disassemble_at(memory, address);
+        address = address.wrapping_add(disassembly.bytes.len() as u16);
+
+        disassembly
+    })
+}
+
+fn disassemble_at<B: Bus>(memory: &B, address: u16) -> Disassembly {
+    let byte_at = |offset: u16| memory.read(address.wrapping_add(offset));
+
+    let instruction = match Instruction::from_byte(byte_at(0)) {
+        Instruction::PREFIX => Instruction::from_prefixed_byte(byte_at(1)),
+        instruction => instruction,
+    };
+    let bytes: Vec<u8> = (0..instruction.length()).map(byte_at).collect();
+
+    // Immediate operands follow the opcode, prefixed instructions have none
+    let operands = Operands {
+        low: bytes.get(1).copied().unwrap_or(0),
+        high: bytes.get(2).copied().unwrap_or(0),
+        next_address: address.wrapping_add(bytes.len() as u16),
+    };
+
+    Disassembly {
+        address,
+        text: Syntax::VALUES(operands).format(instruction),
+        instruction,
+        bytes,
+    }
+}
+
+impl fmt::Display for Instruction {
+    /// Formats the instruction in RGBDS syntax, immediate operands are shown as placeholders (n8, n16, a8, a16, e8).
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&Syntax::PLACEHOLDERS.format(*self))
+    }
+}
+
+/// The immediate operand bytes that follow an opcode.
+#[derive(Clone, Copy)]
+struct Operands {
+    low: u8,
+    high: u8,
+    // Relative jumps are shown as the address they jump to
+    next_address: u16,
+}
+
+/// How immediate operands are written.
+#[derive(Clone, Copy)]
+enum Syntax {
+    PLACEHOLDERS,
+    VALUES(Operands),
+}
+
+impl Syntax {
+    fn format(self, instruction: Instruction) -> String {
+        match instruction {
+            Instruction::ADD(source) => format!("add a, {}", self.byte(source.into())),
+            Instruction::ADC(source) => format!("adc a, {}", self.byte(source.into())),
+            Instruction::AND(source) => format!("and a, {}", self.byte(source.into())),
+            Instruction::CP(source) => format!("cp a, {}", self.byte(source.into())),
+            Instruction::OR(source) => format!("or a, {}", self.byte(source.into())),
+            Instruction::SBC(source) => format!("sbc a, {}", self.byte(source.into())),
+            Instruction::SUB(source) => format!("sub a, {}", self.byte(source.into())),
+            Instruction::XOR(source) => format!("xor a, {}", self.byte(source.into())),
+            Instruction::DEC(target) => format!("dec {}", self.inc_dec(target)),
+            Instruction::INC(target) => format!("inc {}", self.inc_dec(target)),
+            Instruction::ADDHL(source) => format!("add hl, {}", register(source.into())),
+            Instruction::ADDSP => format!("add sp, {}", self.e8()),
+            Instruction::BIT(target, bit) => {
+                format!("bit {}, {}", u8::from(bit), self.location(target.into()))
+            }
+            Instruction::RES(target, bit) => {
+                format!("res {}, {}", u8::from(bit), self.location(target.into()))
+            }
+            Instruction::SET(target, bit) => {
+                format!("set {}, {}", u8::from(bit), self.location(target.into()))
+            }
+            Instruction::SWAP(target) => format!("swap {}", self.location(target.into())),
+            Instruction::RL(target) => format!("rl {}", self.location(target.into())),
+            Instruction::RLC(target) => format!("rlc {}", self.location(target.into())),
+            Instruction::RR(target) => format!("rr {}", self.location(target.into())),
+            Instruction::RRC(target) => format!("rrc {}", self.location(target.into())),
+            Instruction::SLA(target) => format!("sla {}", self.location(target.into())),
+            Instruction::SRA(target) => format!("sra {}", self.location(target.into())),
+            Instruction::SRL(target) => format!("srl {}", self.location(target.into())),
+            Instruction::LD(LoadType::BYTE(target, source)) => format!(
+                "ld {}, {}",
+                self.location(target.into()),
+                self.byte(source.into())
+            ),
+            Instruction::LD(LoadType::WORD(target, source)) => format!(
+                "ld {}, {}",
+                self.word_location(target.into()),
+                self.word(source.into())
+            ),
+            Instruction::LD(LoadType::AFROMINDIRECT(source)) => {
+                format!("{} a, {}", load_mnemonic(source), self.indirect(source))
+            }
+            Instruction::LD(LoadType::INDIRECTFROMA(target)) => {
+                format!("{} {}, a", load_mnemonic(target), self.indirect(target))
+            }
+            Instruction::CALL(test) => format!("call {}{}", condition(test), self.n16()),
+            Instruction::JP(test) => format!("jp {}{}", condition(test), self.n16()),
+            Instruction::JPHL => "jp hl".to_string(),
+            Instruction::JR(test) => format!("jr {}{}", condition(test), self.jr_target()),
+            Instruction::RET(JumpTest::ALWAYS) => "ret".to_string(),
+            Instruction::RET(test) => format!("ret {}", condition(test).trim_end_matches(", ")),
+            Instruction::RETI => "reti".to_string(),
+            Instruction::RST(vector) => format!("rst ${:02X}", u16::from(vector)),
+            Instruction::POP(target) => format!("pop {}", register(target.into())),
+            Instruction::PUSH(source) => format!("push {}", register(source.into())),
+            Instruction::RLA => "rla".to_string(),
+            Instruction::RLCA => "rlca".to_string(),
+            Instruction::RRA => "rra".to_string(),
+            Instruction::RRCA => "rrca".to_string(),
+            Instruction::CCF => "ccf".to_string(),
+            Instruction::CPL => "cpl".to_string(),
+            Instruction::DAA => "daa".to_string(),
+            Instruction::DI => "di".to_string(),
+            Instruction::EI => "ei".to_string(),
+            Instruction::HALT => "halt".to_string(),
+            Instruction::NOP => "nop".to_string(),
+            Instruction::PREFIX => "prefix".to_string(),
+            Instruction::SCF => "scf".to_string(),
+            Instruction::STOP => "stop".to_string(),
+            // There is no mnemonic for illegal opcodes, they can only be written as data
+            Instruction::ILLEGAL(opcode) => format!("db ${:02X}", opcode),
+        }
+    }
+
+    fn byte(self, operand: Operand8) -> String {
+        match operand {
+            Operand8::LOCATION(location) => self.location(location),
+            Operand8::D8 => self.n8(),
+        }
+    }
+
+    fn location(self, location: Location8) -> String {
+        match location {
+            Location8::A => "a".to_string(),
+            Location8::B => "b".to_string(),
+            Location8::C => "c".to_string(),
+            Location8::D => "d".to_string(),
+            Location8::E => "e".to_string(),
+            Location8::H => "h".to_string(),
+            Location8::L => "l".to_string(),
+            Location8::HLI => "[hl]".to_string(),
+            Location8::INDIRECT(indirect) => self.indirect(indirect),
+        }
+    }
+
+    fn word(self, operand: Operand16) -> String {
+        match operand {
+            Operand16::REGISTER(pair) => register(pair).to_string(),
+            Operand16::D16 => self.n16(),
+            Operand16::SPE8 => match self.e8().strip_prefix('-') {
+                Some(offset) => format!("sp-{}", offset),
+                None => format!("sp+{}", self.e8()),
+            },
+        }
+    }
+
+    fn word_location(self, location: Location16) -> String {
+        match location {
+            Location16::REGISTER(pair) => register(pair).to_string(),
+            Location16::A16 => format!("[{}]", self.a16()),
+        }
+    }
+
+    fn inc_dec(self, target: IncDecTarget) -> String {
+        match IncDecOperand::from(target) {
+            IncDecOperand::BYTE(location) => self.location(location),
+            IncDecOperand::WORD(pair) => register(pair).to_string(),
+        }
+    }
+
+    fn indirect(self, indirect: Indirect) -> String {
+        match indirect {
+            Indirect::BC => "[bc]".to_string(),
+            Indirect::DE => "[de]".to_string(),
+            Indirect::HLINC => "[hl+]".to_string(),
+            Indirect::HLDEC => "[hl-]".to_string(),
+            Indirect::HIGHC => "[c]".to_string(),
+            Indirect::HIGHA8 => format!("[{}]", self.a8()),
+            Indirect::A16 => format!("[{}]", self.a16()),
+        }
+    }
+
+    // Immediate operands
+
+    fn n8(self) -> String {
+        match self {
+            Syntax::PLACEHOLDERS => "n8".to_string(),
+            Syntax::VALUES(operands) => format!("${:02X}", operands.low),
+        }
+    }
+
+    fn n16(self) -> String {
+        match self {
+            Syntax::PLACEHOLDERS => "n16".to_string(),
+            Syntax::VALUES(operands) => {
+                format!("${:02X}{:02X}", operands.high, operands.low)
+            }
+        }
+    }
+
+    fn a8(self) -> String {
+        match self {
+            Syntax::PLACEHOLDERS => "a8".to_string(),
+            Syntax::VALUES(operands) => format!("$FF{:02X}", operands.low),
+        }
+    }
+
+    fn a16(self) -> String {
+        match self {
+            Syntax::PLACEHOLDERS => "a16".to_string(),
+            Syntax::VALUES(_) => self.n16(),
+        }
+    }
+
+    fn e8(self) -> String {
+        match self {
+            Syntax::PLACEHOLDERS => "e8".to_string(),
+            Syntax::VALUES(operands) => {
+                let offset = operands.low as i8;
+
+                if offset < 0 {
+                    format!("-${:02X}", offset.unsigned_abs())
+                } else {
+                    format!("${:02X}", offset)
+                }
+            }
+        }
+    }
+
+    fn jr_target(self) -> String {
+        match self {
+            Syntax::PLACEHOLDERS => "e8".to_string(),
+            Syntax::VALUES(operands) => {
+                let offset = operands.low as i8;
+                format!("${:04X}", operands.next_address.wrapping_add(offset as u16))
+            }
+        }
+    }
+}
+
+fn register(pair: Register16) -> &'static str {
+    match pair {
+        Register16::AF => "af",
+        Register16::BC => "bc",
+        Register16::DE => "de",
+        Register16::HL => "hl",
+        Register16::SP => "sp",
+    }
+}
+
+// Loads from and stores into the 0xFF00 page use their own mnemonic
+fn load_mnemonic(indirect: Indirect) -> &'static str {
+    match indirect {
+        Indirect::HIGHC | Indirect::HIGHA8 => "ldh",
+        _ => "ld",
+    }
+}
+
+fn condition(test: JumpTest) -> &'static str {
+    match test {
+        JumpTest::NZ => "nz, ",
+        JumpTest::Z => "z, ",
+        JumpTest::NC => "nc, ",
+        JumpTest::C => "c, ",
+        JumpTest::ALWAYS => "",
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use crate::bus::FlatRam;
+
+    fn setup(program: &[u8]) -> FlatRam {
+        let mut memory = FlatRam::new();
+        memory.load(0x0150, program);
+
+        memory
+    }
+
+    fn texts(program: &[u8], count: usize) -> Vec<String> {
+        let memory = setup(program);
+
+        disassemble(&memory, 0x0150, count)
+            .map(|disassembly| disassembly.text)
+            .collect()
+    }
+
+    #[test]
+    fn displays_placeholders() {
+        assert_eq!(Instruction::from_byte(0x2A).to_string(), "ld a, [hl+]");
+        assert_eq!(Instruction::from_byte(0x20).to_string(), "jr nz, e8");
+        assert_eq!(Instruction::from_byte(0x01).to_string(), "ld bc, n16");
+        assert_eq!(Instruction::from_byte(0xE0).to_string(), "ldh [a8], a");
+        assert_eq!(Instruction::from_byte(0xF8).to_string(), "ld hl, sp+e8");
+        assert_eq!(Instruction::from_byte(0x36).to_string(), "ld [hl], n8");
+        assert_eq!(Instruction::from_byte(0xC8).to_string(), "ret z");
+        assert_eq!(Instruction::from_byte(0xFF).to_string(), "rst $38");
+        assert_eq!(Instruction::from_byte(0xD3).to_string(), "db $D3");
+        assert_eq!(
+            Instruction::from_prefixed_byte(0x7E).to_string(),
+            "bit 7, [hl]"
+        );
+    }
+
+    #[test]
+    fn disassembles_operands() {
+        let program = [
+            0x2A, // ld a, [hl+]
+            0x20, 0xFD, // jr nz, $0150
+            0x08, 0x34, 0xC1, // ld [$C134], sp
+            0xF0, 0x44, // ldh a, [$FF44]
+            0xE8, 0xFE, // add sp, -$02
+            0xF8, 0x05, // ld hl, sp+$05
+            0xCB, 0x11, // rl c
+            0xCD, 0x00, 0x02, // call $0200
+            0xFE, 0x90, // cp a, $90
+        ];
+
+        assert_eq!(
+            texts(&program, 9),
+            [
+                "ld a, [hl+]",
+                "jr nz, $0150",
+                "ld [$C134], sp",
+                "ldh a, [$FF44]",
+                "add sp, -$02",
+                "ld hl, sp+$05",
+                "rl c",
+                "call $0200",
+                "cp a, $90",
+            ]
+        );
+    }
+
+    #[test]
+    fn disassembly_lists_address_and_bytes() {
+        let memory = setup(&[0x00, 0xC3, 0x50, 0x01]);
+        let disassembly: Vec<Disassembly> = disassemble(&memory, 0x0150, 2).collect();
+
+        assert_eq!(disassembly[1].address, 0x0151);
+        assert_eq!(disassembly[1].bytes, [0xC3, 0x50, 0x01]);
+        assert_eq!(disassembly[1].to_string(), "0151: C3 50 01  jp $0150");
+    }
+}
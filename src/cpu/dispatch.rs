@@ -0,0 +1,109 @@
+//! # Dispatch table
+//!
+//! Every opcode gets its own handler function with the decoded instruction baked in at compile time, so executing an
+//! instruction is an index into the table instead of decoding the opcode and matching on the decoded instruction.
+//!
+//! The first 256 entries are the unprefixed opcodes, the last 256 the opcodes that follow a `0xCB` prefix byte.
+
+use super::instructions::Instruction;
+use super::CPU;
+use crate::bus::Bus;
+use std::marker::PhantomData;
+
+/// The handlers for the 16 opcodes in a row of the opcode table, `0x$row0` to `0x$rowF`.
+macro_rules! handler_row {
+    ($handler:ident, $row:literal) => {
+        [
+            $handler::<B, { $row << 4 }>,
+            $handler::<B, { $row << 4 | 0x1 }>,
+            $handler::<B, { $row << 4 | 0x2 }>,
+            $handler::<B, { $row << 4 | 0x3 }>,
+            $handler::<B, { $row << 4 | 0x4 }>,
+            $handler::<B, { $row << 4 | 0x5 }>,
+            $handler::<B, { $row << 4 | 0x6 }>,
+            $handler::<B, { $row << 4 | 0x7 }>,
+            $handler::<B, { $row << 4 | 0x8 }>,
+            $handler::<B, { $row << 4 | 0x9 }>,
+            $handler::<B, { $row << 4 | 0xA }>,
+            $handler::<B, { $row << 4 | 0xB }>,
+            $handler::<B, { $row << 4 | 0xC }>,
+            $handler::<B, { $row << 4 | 0xD }>,
+            $handler::<B, { $row << 4 | 0xE }>,
+            $handler::<B, { $row << 4 | 0xF }>,
+        ]
+    };
+}
+
+pub type Handler<B> = fn(&mut CPU<B>);
+
+/// The dispatch table of a CPU connected to a `B`, handlers are monomorphized for every kind of bus.
+pub struct DispatchTable<B>(PhantomData<B>);
+
+impl<B: Bus> DispatchTable<B> {
+    pub const HANDLERS: [Handler<B>; 512] = flatten([
+        handler_row!(execute_opcode, 0x0),
+        handler_row!(execute_opcode, 0x1),
+        handler_row!(execute_opcode, 0x2),
+        handler_row!(execute_opcode, 0x3),
+        handler_row!(execute_opcode, 0x4),
+        handler_row!(execute_opcode, 0x5),
+        handler_row!(execute_opcode, 0x6),
+        handler_row!(execute_opcode, 0x7),
+        handler_row!(execute_opcode, 0x8),
+        handler_row!(execute_opcode, 0x9),
+        handler_row!(execute_opcode, 0xA),
+        handler_row!(execute_opcode, 0xB),
+        handler_row!(execute_opcode, 0xC),
+        handler_row!(execute_opcode, 0xD),
+        handler_row!(execute_opcode, 0xE),
+        handler_row!(execute_opcode, 0xF),
+        handler_row!(execute_prefixed_opcode, 0x0),
+        handler_row!(execute_prefixed_opcode, 0x1),
+        handler_row!(execute_prefixed_opcode, 0x2),
+        handler_row!(execute_prefixed_opcode, 0x3),
+        handler_row!(execute_prefixed_opcode, 0x4),
+        handler_row!(execute_prefixed_opcode, 0x5),
+        handler_row!(execute_prefixed_opcode, 0x6),
+        handler_row!(execute_prefixed_opcode, 0x7),
+        handler_row!(execute_prefixed_opcode, 0x8),
+        handler_row!(execute_prefixed_opcode, 0x9),
+        handler_row!(execute_prefixed_opcode, 0xA),
+        handler_row!(execute_prefixed_opcode, 0xB),
+        handler_row!(execute_prefixed_opcode, 0xC),
+        handler_row!(execute_prefixed_opcode, 0xD),
+        handler_row!(execute_prefixed_opcode, 0xE),
+        handler_row!(execute_prefixed_opcode, 0xF),
+    ]);
+
+    // Indexes a reference to the table, indexing `HANDLERS` directly would copy the whole table every time
+    #[inline(always)]
+    pub fn handler(index: usize) -> Handler<B> {
+        let table = const { &Self::HANDLERS };
+        table[index]
+    }
+}
+
+const fn flatten<B: Bus>(rows: [[Handler<B>; 16]; 32]) -> [Handler<B>; 512] {
+    let mut table: [Handler<B>; 512] = [execute_opcode::<B, 0x00>; 512];
+    let mut index = 0;
+
+    while index < table.len() {
+        table[index] = rows[index >> 4][index & 0xF];
+        index += 1;
+    }
+
+    table
+}
+
+fn execute_opcode<B: Bus, const OPCODE: u8>(cpu: &mut CPU<B>) {
+    if OPCODE == 0xCB {
+        let opcode = cpu.read_next_byte();
+        DispatchTable::<B>::handler(0x100 | opcode as usize)(cpu);
+    } else {
+        cpu.execute(const { Instruction::from_byte(OPCODE) });
+    }
+}
+
+fn execute_prefixed_opcode<B: Bus, const OPCODE: u8>(cpu: &mut CPU<B>) {
+    cpu.execute(const { Instruction::from_prefixed_byte(OPCODE) });
+}
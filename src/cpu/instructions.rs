@@ -1,5 +1,4 @@
-use std;
-
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ArithmeticTarget {
     A,
     B,
@@ -9,8 +8,10 @@ pub enum ArithmeticTarget {
     H,
     L,
     HLI,
+    D8,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum IncDecTarget {
     A,
     B,
@@ -26,6 +27,7 @@ pub enum IncDecTarget {
     SP,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ADDHLTarget {
     BC,
     DE,
@@ -33,6 +35,7 @@ pub enum ADDHLTarget {
     SP,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum PrefixTarget {
     A,
     B,
@@ -44,6 +47,7 @@ pub enum PrefixTarget {
     HLI,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum BitPosition {
     B0,
     B1,
@@ -55,6 +59,27 @@ pub enum BitPosition {
     B7,
 }
 
+impl BitPosition {
+    const fn from_index(index: u8) -> Self {
+        match index & 0b111 {
+            0 => BitPosition::B0,
+            1 => BitPosition::B1,
+            2 => BitPosition::B2,
+            3 => BitPosition::B3,
+            4 => BitPosition::B4,
+            5 => BitPosition::B5,
+            6 => BitPosition::B6,
+            _ => BitPosition::B7,
+        }
+    }
+}
+
+impl std::convert::From<u8> for BitPosition {
+    fn from(value: u8) -> Self {
+        BitPosition::from_index(value)
+    }
+}
+
 impl std::convert::From<BitPosition> for u8 {
     fn from(value: BitPosition) -> Self {
         match value {
@@ -70,6 +95,7 @@ impl std::convert::From<BitPosition> for u8 {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum LoadByteTarget {
     A,
     B,
@@ -81,6 +107,7 @@ pub enum LoadByteTarget {
     HLI,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum LoadByteSource {
     A,
     B,
@@ -90,19 +117,71 @@ pub enum LoadByteSource {
     H,
     L,
     HLI,
+    D8,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum LoadWordTarget {
     BC,
     DE,
     HL,
+    SP,
+    A16,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoadWordSource {
+    D16,
+    HL,
+    SP,
+    SPE8,
+}
+
+/// Memory locations that can only be loaded from or stored into the `a` register.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Indirect {
+    // (BC)
+    BC,
+    // (DE)
+    DE,
+    // (HL+)
+    HLINC,
+    // (HL-)
+    HLDEC,
+    // (0xFF00 + C)
+    HIGHC,
+    // (0xFF00 + a8)
+    HIGHA8,
+    // (a16)
+    A16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum LoadType {
-    BYTE(LoadByteSource, LoadByteTarget),
-    // WORD(LoadWordTarget),
+    BYTE(LoadByteTarget, LoadByteSource),
+    WORD(LoadWordTarget, LoadWordSource),
+    AFROMINDIRECT(Indirect),
+    INDIRECTFROMA(Indirect),
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JumpTest {
+    NZ,
+    Z,
+    NC,
+    C,
+    ALWAYS,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StackTarget {
+    BC,
+    DE,
+    HL,
+    AF,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum RSTVector {
     X00,
     X08,
@@ -114,6 +193,22 @@ pub enum RSTVector {
     X38,
 }
 
+impl std::convert::From<RSTVector> for u16 {
+    fn from(value: RSTVector) -> Self {
+        match value {
+            RSTVector::X00 => 0x00,
+            RSTVector::X08 => 0x08,
+            RSTVector::X10 => 0x10,
+            RSTVector::X18 => 0x18,
+            RSTVector::X20 => 0x20,
+            RSTVector::X28 => 0x28,
+            RSTVector::X30 => 0x30,
+            RSTVector::X38 => 0x38,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Interrupts {
     VBLANK,
     STAT,
@@ -122,6 +217,41 @@ pub enum Interrupts {
     JOYPAD,
 }
 
+impl Interrupts {
+    /// Every interrupt from the highest to the lowest priority.
+    pub const PRIORITY: [Interrupts; 5] = [
+        Interrupts::VBLANK,
+        Interrupts::STAT,
+        Interrupts::TIMER,
+        Interrupts::SERIAL,
+        Interrupts::JOYPAD,
+    ];
+
+    /// The bit of this interrupt in the IF and IE registers.
+    pub fn mask(self) -> u8 {
+        match self {
+            Interrupts::VBLANK => 0b0000_0001,
+            Interrupts::STAT => 0b0000_0010,
+            Interrupts::TIMER => 0b0000_0100,
+            Interrupts::SERIAL => 0b0000_1000,
+            Interrupts::JOYPAD => 0b0001_0000,
+        }
+    }
+}
+
+impl std::convert::From<Interrupts> for u16 {
+    fn from(value: Interrupts) -> Self {
+        match value {
+            Interrupts::VBLANK => 0x40,
+            Interrupts::STAT => 0x48,
+            Interrupts::TIMER => 0x50,
+            Interrupts::SERIAL => 0x58,
+            Interrupts::JOYPAD => 0x60,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Instruction {
     // 8 bit instructions
     ADD(ArithmeticTarget),
@@ -137,9 +267,11 @@ pub enum Instruction {
 
     // 16 bit instructions
     ADDHL(ADDHLTarget),
+    ADDSP,
 
     // Bit operations
     BIT(PrefixTarget, BitPosition),
+    RES(PrefixTarget, BitPosition),
     SET(PrefixTarget, BitPosition),
     SWAP(PrefixTarget),
 
@@ -152,15 +284,519 @@ pub enum Instruction {
     RRA,
     RRC(PrefixTarget),
     RRCA,
+    SLA(PrefixTarget),
+    SRA(PrefixTarget),
+    SRL(PrefixTarget),
 
     // Load instructions
     LD(LoadType),
 
     // Jumps and Subroutines
-    // RST(RSTVector),
+    CALL(JumpTest),
+    JP(JumpTest),
+    JPHL,
+    JR(JumpTest),
+    RET(JumpTest),
+    RETI,
+    RST(RSTVector),
+
+    // Stack instructions
+    POP(StackTarget),
+    PUSH(StackTarget),
 
     // Misc instructions
     CCF,
     CPL,
+    DAA,
+    DI,
+    EI,
+    HALT,
+    NOP,
+    PREFIX,
     SCF,
+    STOP,
+
+    // One of the opcodes that has no instruction assigned to it on the SM83
+    ILLEGAL(u8),
+}
+
+impl Instruction {
+    /// Decodes an unprefixed opcode.
+    ///
+    /// Any immediate operands (d8, d16, a8, a16, e8) are not part of the returned instruction, they are read from the
+    /// instruction stream by the CPU when the instruction is executed. `0xCB` decodes to `Instruction::PREFIX`.
+    pub const fn from_byte(byte: u8) -> Instruction {
+        match byte {
+            0x00 => Instruction::NOP,
+            0x01 => Instruction::LD(LoadType::WORD(LoadWordTarget::BC, LoadWordSource::D16)),
+            0x02 => Instruction::LD(LoadType::INDIRECTFROMA(Indirect::BC)),
+            0x03 => Instruction::INC(IncDecTarget::BC),
+            0x04 => Instruction::INC(IncDecTarget::B),
+            0x05 => Instruction::DEC(IncDecTarget::B),
+            0x06 => Instruction::LD(LoadType::BYTE(LoadByteTarget::B, LoadByteSource::D8)),
+            0x07 => Instruction::RLCA,
+            0x08 => Instruction::LD(LoadType::WORD(LoadWordTarget::A16, LoadWordSource::SP)),
+            0x09 => Instruction::ADDHL(ADDHLTarget::BC),
+            0x0A => Instruction::LD(LoadType::AFROMINDIRECT(Indirect::BC)),
+            0x0B => Instruction::DEC(IncDecTarget::BC),
+            0x0C => Instruction::INC(IncDecTarget::C),
+            0x0D => Instruction::DEC(IncDecTarget::C),
+            0x0E => Instruction::LD(LoadType::BYTE(LoadByteTarget::C, LoadByteSource::D8)),
+            0x0F => Instruction::RRCA,
+
+            0x10 => Instruction::STOP,
+            0x11 => Instruction::LD(LoadType::WORD(LoadWordTarget::DE, LoadWordSource::D16)),
+            0x12 => Instruction::LD(LoadType::INDIRECTFROMA(Indirect::DE)),
+            0x13 => Instruction::INC(IncDecTarget::DE),
+            0x14 => Instruction::INC(IncDecTarget::D),
+            0x15 => Instruction::DEC(IncDecTarget::D),
+            0x16 => Instruction::LD(LoadType::BYTE(LoadByteTarget::D, LoadByteSource::D8)),
+            0x17 => Instruction::RLA,
+            0x18 => Instruction::JR(JumpTest::ALWAYS),
+            0x19 => Instruction::ADDHL(ADDHLTarget::DE),
+            0x1A => Instruction::LD(LoadType::AFROMINDIRECT(Indirect::DE)),
+            0x1B => Instruction::DEC(IncDecTarget::DE),
+            0x1C => Instruction::INC(IncDecTarget::E),
+            0x1D => Instruction::DEC(IncDecTarget::E),
+            0x1E => Instruction::LD(LoadType::BYTE(LoadByteTarget::E, LoadByteSource::D8)),
+            0x1F => Instruction::RRA,
+
+            0x20 => Instruction::JR(JumpTest::NZ),
+            0x21 => Instruction::LD(LoadType::WORD(LoadWordTarget::HL, LoadWordSource::D16)),
+            0x22 => Instruction::LD(LoadType::INDIRECTFROMA(Indirect::HLINC)),
+            0x23 => Instruction::INC(IncDecTarget::HL),
+            0x24 => Instruction::INC(IncDecTarget::H),
+            0x25 => Instruction::DEC(IncDecTarget::H),
+            0x26 => Instruction::LD(LoadType::BYTE(LoadByteTarget::H, LoadByteSource::D8)),
+            0x27 => Instruction::DAA,
+            0x28 => Instruction::JR(JumpTest::Z),
+            0x29 => Instruction::ADDHL(ADDHLTarget::HL),
+            0x2A => Instruction::LD(LoadType::AFROMINDIRECT(Indirect::HLINC)),
+            0x2B => Instruction::DEC(IncDecTarget::HL),
+            0x2C => Instruction::INC(IncDecTarget::L),
+            0x2D => Instruction::DEC(IncDecTarget::L),
+            0x2E => Instruction::LD(LoadType::BYTE(LoadByteTarget::L, LoadByteSource::D8)),
+            0x2F => Instruction::CPL,
+
+            0x30 => Instruction::JR(JumpTest::NC),
+            0x31 => Instruction::LD(LoadType::WORD(LoadWordTarget::SP, LoadWordSource::D16)),
+            0x32 => Instruction::LD(LoadType::INDIRECTFROMA(Indirect::HLDEC)),
+            0x33 => Instruction::INC(IncDecTarget::SP),
+            0x34 => Instruction::INC(IncDecTarget::HLI),
+            0x35 => Instruction::DEC(IncDecTarget::HLI),
+            0x36 => Instruction::LD(LoadType::BYTE(LoadByteTarget::HLI, LoadByteSource::D8)),
+            0x37 => Instruction::SCF,
+            0x38 => Instruction::JR(JumpTest::C),
+            0x39 => Instruction::ADDHL(ADDHLTarget::SP),
+            0x3A => Instruction::LD(LoadType::AFROMINDIRECT(Indirect::HLDEC)),
+            0x3B => Instruction::DEC(IncDecTarget::SP),
+            0x3C => Instruction::INC(IncDecTarget::A),
+            0x3D => Instruction::DEC(IncDecTarget::A),
+            0x3E => Instruction::LD(LoadType::BYTE(LoadByteTarget::A, LoadByteSource::D8)),
+            0x3F => Instruction::CCF,
+
+            // `LD (HL),(HL)` sits where HALT is in the 8 bit load block
+            0x76 => Instruction::HALT,
+            0x40..=0x7F => Instruction::LD(LoadType::BYTE(
+                LoadByteTarget::from_index(byte >> 3),
+                LoadByteSource::from_index(byte),
+            )),
+
+            0x80..=0x87 => Instruction::ADD(ArithmeticTarget::from_index(byte)),
+            0x88..=0x8F => Instruction::ADC(ArithmeticTarget::from_index(byte)),
+            0x90..=0x97 => Instruction::SUB(ArithmeticTarget::from_index(byte)),
+            0x98..=0x9F => Instruction::SBC(ArithmeticTarget::from_index(byte)),
+            0xA0..=0xA7 => Instruction::AND(ArithmeticTarget::from_index(byte)),
+            0xA8..=0xAF => Instruction::XOR(ArithmeticTarget::from_index(byte)),
+            0xB0..=0xB7 => Instruction::OR(ArithmeticTarget::from_index(byte)),
+            0xB8..=0xBF => Instruction::CP(ArithmeticTarget::from_index(byte)),
+
+            0xC0 => Instruction::RET(JumpTest::NZ),
+            0xC1 => Instruction::POP(StackTarget::BC),
+            0xC2 => Instruction::JP(JumpTest::NZ),
+            0xC3 => Instruction::JP(JumpTest::ALWAYS),
+            0xC4 => Instruction::CALL(JumpTest::NZ),
+            0xC5 => Instruction::PUSH(StackTarget::BC),
+            0xC6 => Instruction::ADD(ArithmeticTarget::D8),
+            0xC7 => Instruction::RST(RSTVector::X00),
+            0xC8 => Instruction::RET(JumpTest::Z),
+            0xC9 => Instruction::RET(JumpTest::ALWAYS),
+            0xCA => Instruction::JP(JumpTest::Z),
+            0xCB => Instruction::PREFIX,
+            0xCC => Instruction::CALL(JumpTest::Z),
+            0xCD => Instruction::CALL(JumpTest::ALWAYS),
+            0xCE => Instruction::ADC(ArithmeticTarget::D8),
+            0xCF => Instruction::RST(RSTVector::X08),
+
+            0xD0 => Instruction::RET(JumpTest::NC),
+            0xD1 => Instruction::POP(StackTarget::DE),
+            0xD2 => Instruction::JP(JumpTest::NC),
+            0xD4 => Instruction::CALL(JumpTest::NC),
+            0xD5 => Instruction::PUSH(StackTarget::DE),
+            0xD6 => Instruction::SUB(ArithmeticTarget::D8),
+            0xD7 => Instruction::RST(RSTVector::X10),
+            0xD8 => Instruction::RET(JumpTest::C),
+            0xD9 => Instruction::RETI,
+            0xDA => Instruction::JP(JumpTest::C),
+            0xDC => Instruction::CALL(JumpTest::C),
+            0xDE => Instruction::SBC(ArithmeticTarget::D8),
+            0xDF => Instruction::RST(RSTVector::X18),
+
+            0xE0 => Instruction::LD(LoadType::INDIRECTFROMA(Indirect::HIGHA8)),
+            0xE1 => Instruction::POP(StackTarget::HL),
+            0xE2 => Instruction::LD(LoadType::INDIRECTFROMA(Indirect::HIGHC)),
+            0xE5 => Instruction::PUSH(StackTarget::HL),
+            0xE6 => Instruction::AND(ArithmeticTarget::D8),
+            0xE7 => Instruction::RST(RSTVector::X20),
+            0xE8 => Instruction::ADDSP,
+            0xE9 => Instruction::JPHL,
+            0xEA => Instruction::LD(LoadType::INDIRECTFROMA(Indirect::A16)),
+            0xEE => Instruction::XOR(ArithmeticTarget::D8),
+            0xEF => Instruction::RST(RSTVector::X28),
+
+            0xF0 => Instruction::LD(LoadType::AFROMINDIRECT(Indirect::HIGHA8)),
+            0xF1 => Instruction::POP(StackTarget::AF),
+            0xF2 => Instruction::LD(LoadType::AFROMINDIRECT(Indirect::HIGHC)),
+            0xF3 => Instruction::DI,
+            0xF5 => Instruction::PUSH(StackTarget::AF),
+            0xF6 => Instruction::OR(ArithmeticTarget::D8),
+            0xF7 => Instruction::RST(RSTVector::X30),
+            0xF8 => Instruction::LD(LoadType::WORD(LoadWordTarget::HL, LoadWordSource::SPE8)),
+            0xF9 => Instruction::LD(LoadType::WORD(LoadWordTarget::SP, LoadWordSource::HL)),
+            0xFA => Instruction::LD(LoadType::AFROMINDIRECT(Indirect::A16)),
+            0xFB => Instruction::EI,
+            0xFE => Instruction::CP(ArithmeticTarget::D8),
+            0xFF => Instruction::RST(RSTVector::X38),
+
+            0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD => {
+                Instruction::ILLEGAL(byte)
+            }
+        }
+    }
+
+    /// The number of bytes the instruction takes up in memory, including the opcode, the `0xCB` prefix byte and any
+    /// immediate operands.
+    pub fn length(&self) -> u16 {
+        match self {
+            _ if self.is_prefixed() => 2,
+            Instruction::ADD(ArithmeticTarget::D8)
+            | Instruction::ADC(ArithmeticTarget::D8)
+            | Instruction::AND(ArithmeticTarget::D8)
+            | Instruction::CP(ArithmeticTarget::D8)
+            | Instruction::OR(ArithmeticTarget::D8)
+            | Instruction::SBC(ArithmeticTarget::D8)
+            | Instruction::SUB(ArithmeticTarget::D8)
+            | Instruction::XOR(ArithmeticTarget::D8)
+            | Instruction::LD(LoadType::BYTE(_, LoadByteSource::D8))
+            | Instruction::LD(LoadType::WORD(_, LoadWordSource::SPE8))
+            | Instruction::LD(LoadType::AFROMINDIRECT(Indirect::HIGHA8))
+            | Instruction::LD(LoadType::INDIRECTFROMA(Indirect::HIGHA8))
+            | Instruction::ADDSP
+            | Instruction::JR(_)
+            | Instruction::STOP => 2,
+            Instruction::LD(LoadType::WORD(LoadWordTarget::A16, _))
+            | Instruction::LD(LoadType::WORD(_, LoadWordSource::D16))
+            | Instruction::LD(LoadType::AFROMINDIRECT(Indirect::A16))
+            | Instruction::LD(LoadType::INDIRECTFROMA(Indirect::A16))
+            | Instruction::CALL(_)
+            | Instruction::JP(_) => 3,
+            _ => 1,
+        }
+    }
+
+    /// Whether the instruction is encoded behind a `0xCB` prefix byte.
+    pub fn is_prefixed(&self) -> bool {
+        matches!(
+            self,
+            Instruction::BIT(..)
+                | Instruction::RES(..)
+                | Instruction::SET(..)
+                | Instruction::SWAP(_)
+                | Instruction::RL(_)
+                | Instruction::RLC(_)
+                | Instruction::RR(_)
+                | Instruction::RRC(_)
+                | Instruction::SLA(_)
+                | Instruction::SRA(_)
+                | Instruction::SRL(_)
+        )
+    }
+
+    /// Decodes the opcode that follows a `0xCB` prefix byte.
+    ///
+    /// Every one of the 256 prefixed opcodes is defined. The lowest 3 bits select the target and, for BIT, RES and SET,
+    /// the next 3 bits select the bit position.
+    pub const fn from_prefixed_byte(byte: u8) -> Instruction {
+        let target = PrefixTarget::from_index(byte);
+        let bit_position = BitPosition::from_index(byte >> 3);
+
+        match byte {
+            0x00..=0x07 => Instruction::RLC(target),
+            0x08..=0x0F => Instruction::RRC(target),
+            0x10..=0x17 => Instruction::RL(target),
+            0x18..=0x1F => Instruction::RR(target),
+            0x20..=0x27 => Instruction::SLA(target),
+            0x28..=0x2F => Instruction::SRA(target),
+            0x30..=0x37 => Instruction::SWAP(target),
+            0x38..=0x3F => Instruction::SRL(target),
+            0x40..=0x7F => Instruction::BIT(target, bit_position),
+            0x80..=0xBF => Instruction::RES(target, bit_position),
+            0xC0..=0xFF => Instruction::SET(target, bit_position),
+        }
+    }
+}
+
+// Opcodes encode 8 bit registers in 3 bits in the order B, C, D, E, H, L, (HL), A.
+// Only the lowest 3 bits of `index` are used.
+
+impl ArithmeticTarget {
+    const fn from_index(index: u8) -> Self {
+        match index & 0b111 {
+            0 => ArithmeticTarget::B,
+            1 => ArithmeticTarget::C,
+            2 => ArithmeticTarget::D,
+            3 => ArithmeticTarget::E,
+            4 => ArithmeticTarget::H,
+            5 => ArithmeticTarget::L,
+            6 => ArithmeticTarget::HLI,
+            _ => ArithmeticTarget::A,
+        }
+    }
+}
+
+impl PrefixTarget {
+    const fn from_index(index: u8) -> Self {
+        match index & 0b111 {
+            0 => PrefixTarget::B,
+            1 => PrefixTarget::C,
+            2 => PrefixTarget::D,
+            3 => PrefixTarget::E,
+            4 => PrefixTarget::H,
+            5 => PrefixTarget::L,
+            6 => PrefixTarget::HLI,
+            _ => PrefixTarget::A,
+        }
+    }
+}
+
+impl LoadByteTarget {
+    const fn from_index(index: u8) -> Self {
+        match index & 0b111 {
+            0 => LoadByteTarget::B,
+            1 => LoadByteTarget::C,
+            2 => LoadByteTarget::D,
+            3 => LoadByteTarget::E,
+            4 => LoadByteTarget::H,
+            5 => LoadByteTarget::L,
+            6 => LoadByteTarget::HLI,
+            _ => LoadByteTarget::A,
+        }
+    }
+}
+
+impl LoadByteSource {
+    const fn from_index(index: u8) -> Self {
+        match index & 0b111 {
+            0 => LoadByteSource::B,
+            1 => LoadByteSource::C,
+            2 => LoadByteSource::D,
+            3 => LoadByteSource::E,
+            4 => LoadByteSource::H,
+            5 => LoadByteSource::L,
+            6 => LoadByteSource::HLI,
+            _ => LoadByteSource::A,
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    #[test]
+    fn decodes_every_opcode() {
+        let illegal = [
+            0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD,
+        ];
+
+        for byte in 0..=0xFF {
+            let instruction = Instruction::from_byte(byte);
+
+            assert_eq!(
+                instruction == Instruction::ILLEGAL(byte),
+                illegal.contains(&byte),
+                "opcode 0x{:02X} decoded to {:?}",
+                byte,
+                instruction
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_8bit_loads() {
+        assert_eq!(
+            Instruction::from_byte(0x41),
+            Instruction::LD(LoadType::BYTE(LoadByteTarget::B, LoadByteSource::C))
+        );
+        assert_eq!(
+            Instruction::from_byte(0x7E),
+            Instruction::LD(LoadType::BYTE(LoadByteTarget::A, LoadByteSource::HLI))
+        );
+        assert_eq!(
+            Instruction::from_byte(0x77),
+            Instruction::LD(LoadType::BYTE(LoadByteTarget::HLI, LoadByteSource::A))
+        );
+        assert_eq!(
+            Instruction::from_byte(0x36),
+            Instruction::LD(LoadType::BYTE(LoadByteTarget::HLI, LoadByteSource::D8))
+        );
+        assert_eq!(Instruction::from_byte(0x76), Instruction::HALT);
+    }
+
+    #[test]
+    fn decodes_arithmetic() {
+        assert_eq!(
+            Instruction::from_byte(0x80),
+            Instruction::ADD(ArithmeticTarget::B)
+        );
+        assert_eq!(
+            Instruction::from_byte(0x9E),
+            Instruction::SBC(ArithmeticTarget::HLI)
+        );
+        assert_eq!(
+            Instruction::from_byte(0xBF),
+            Instruction::CP(ArithmeticTarget::A)
+        );
+        assert_eq!(
+            Instruction::from_byte(0xE6),
+            Instruction::AND(ArithmeticTarget::D8)
+        );
+    }
+
+    #[test]
+    fn decodes_immediate_forms() {
+        assert_eq!(
+            Instruction::from_byte(0x31),
+            Instruction::LD(LoadType::WORD(LoadWordTarget::SP, LoadWordSource::D16))
+        );
+        assert_eq!(
+            Instruction::from_byte(0xE0),
+            Instruction::LD(LoadType::INDIRECTFROMA(Indirect::HIGHA8))
+        );
+        assert_eq!(
+            Instruction::from_byte(0xFA),
+            Instruction::LD(LoadType::AFROMINDIRECT(Indirect::A16))
+        );
+        assert_eq!(Instruction::from_byte(0x20), Instruction::JR(JumpTest::NZ));
+        assert_eq!(
+            Instruction::from_byte(0xCD),
+            Instruction::CALL(JumpTest::ALWAYS)
+        );
+    }
+
+    #[test]
+    fn decodes_prefixed_shifts_and_rotates() {
+        assert_eq!(
+            Instruction::from_prefixed_byte(0x00),
+            Instruction::RLC(PrefixTarget::B)
+        );
+        assert_eq!(
+            Instruction::from_prefixed_byte(0x0E),
+            Instruction::RRC(PrefixTarget::HLI)
+        );
+        assert_eq!(
+            Instruction::from_prefixed_byte(0x17),
+            Instruction::RL(PrefixTarget::A)
+        );
+        assert_eq!(
+            Instruction::from_prefixed_byte(0x1B),
+            Instruction::RR(PrefixTarget::E)
+        );
+        assert_eq!(
+            Instruction::from_prefixed_byte(0x24),
+            Instruction::SLA(PrefixTarget::H)
+        );
+        assert_eq!(
+            Instruction::from_prefixed_byte(0x2D),
+            Instruction::SRA(PrefixTarget::L)
+        );
+        assert_eq!(
+            Instruction::from_prefixed_byte(0x37),
+            Instruction::SWAP(PrefixTarget::A)
+        );
+        assert_eq!(
+            Instruction::from_prefixed_byte(0x3A),
+            Instruction::SRL(PrefixTarget::D)
+        );
+    }
+
+    #[test]
+    fn decodes_prefixed_bit_operations() {
+        assert_eq!(
+            Instruction::from_prefixed_byte(0x40),
+            Instruction::BIT(PrefixTarget::B, BitPosition::B0)
+        );
+        assert_eq!(
+            Instruction::from_prefixed_byte(0x7E),
+            Instruction::BIT(PrefixTarget::HLI, BitPosition::B7)
+        );
+        assert_eq!(
+            Instruction::from_prefixed_byte(0x99),
+            Instruction::RES(PrefixTarget::C, BitPosition::B3)
+        );
+        assert_eq!(
+            Instruction::from_prefixed_byte(0xEF),
+            Instruction::SET(PrefixTarget::A, BitPosition::B5)
+        );
+        assert_eq!(
+            Instruction::from_prefixed_byte(0xFF),
+            Instruction::SET(PrefixTarget::A, BitPosition::B7)
+        );
+    }
+
+    #[test]
+    fn instruction_lengths() {
+        let lengths = [
+            (0x00, 1),
+            (0x01, 3),
+            (0x06, 2),
+            (0x08, 3),
+            (0x10, 2),
+            (0x18, 2),
+            (0x36, 2),
+            (0x7E, 1),
+            (0xC3, 3),
+            (0xCD, 3),
+            (0xE0, 2),
+            (0xE2, 1),
+            (0xE8, 2),
+            (0xEA, 3),
+            (0xF8, 2),
+            (0xFE, 2),
+        ];
+
+        for (opcode, length) in lengths {
+            assert_eq!(
+                Instruction::from_byte(opcode).length(),
+                length,
+                "{:02X}",
+                opcode
+            );
+        }
+
+        for opcode in 0..=0xFF {
+            assert_eq!(Instruction::from_prefixed_byte(opcode).length(), 2);
+        }
+    }
+
+    #[test]
+    fn rst_vector_addresses() {
+        assert_eq!(u16::from(RSTVector::X00), 0x00);
+        assert_eq!(u16::from(RSTVector::X18), 0x18);
+        assert_eq!(u16::from(RSTVector::X38), 0x38);
+        assert_eq!(
+            Instruction::from_byte(0xEF),
+            Instruction::RST(RSTVector::X28)
+        );
+    }
 }
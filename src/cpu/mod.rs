@@ -1,268 +1,559 @@
+pub mod disassembler;
+mod dispatch;
 mod instructions;
+mod operands;
 mod registers;
 
+use crate::bus::*;
 use crate::memory::*;
+use crate::model::Model;
+use dispatch::DispatchTable;
 use instructions::*;
+use operands::*;
 use registers::Registers;
 
-/// # CPU Macros
-///
-/// These macros make it easier to read/write from/to either 8 or 16 bit registers to cut down on repeated code.
-///
-/// # 8 bit register macros
-macro_rules! operate_8bit_register {
-    // Operate on an 8 bit register and return the value
-    //
-    // The following macro pattern breaks down like this:
-    // operate_8bit_register!(a => self.fn)
-    ($getter:ident => $self:ident.$fn:ident) => {
-        {
-            $self.$fn($self.registers.$getter)
-        }
-    };
-
-    // Set the result of an 8 bit register operation into another register
-    //
-    // The following macro pattern breaks down like this:
-    // operate_8bit_register!(b => self.fn => a)
-    ($getter:ident => $self:ident.$fn:ident => $setter:ident) => {
-        {
-            $self.registers.$setter = operate_8bit_register!($getter => $self.$fn);
-        }
-    };
-
-    ($getter:ident => $self:ident.$fn:ident @ $arg:expr) => {
-        {
-            $self.$fn($self.registers.$getter, $arg);
-        }
-    };
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CPUState {
+    RUNNING,
+    // Woken up by any interrupt that is enabled in IE, even when `ime` is disabled
+    HALTED,
+    // Woken up by joypad input
+    STOPPED,
+    // An illegal opcode was executed, only a reset gets the CPU out of this state
+    LOCKED(CPULockup),
+}
 
-    ($getter:ident => ($self:ident.$fn:ident @ $arg:expr) => $setter:ident) => {
-        {
-            $self.registers.$setter = $self.$fn($self.registers.$getter, $arg);
-        }
-    };
+/// Where and why the CPU locked up after executing one of the illegal opcodes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CPULockup {
+    pub opcode: u8,
+    pub pc: u16,
+    pub bank: u8,
 }
 
-macro_rules! operate_16bit_register {
-    // Get the value of a 16 bit register
-    //
-    // The following macro pattern breaks down like this:
-    // operate_8bit_register!(b => self.add)
-    ($getter:ident => $self:ident.$fn:ident => $setter:ident) => {{
-        let register = $self.registers.$getter();
-        let new_value = $self.$fn(register);
-        $self.registers.$setter(new_value);
-    }};
+impl std::fmt::Display for CPULockup {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "CPU locked up on illegal opcode 0x{:02X} at {:02X}:{:04X}",
+            self.opcode, self.bank, self.pc
+        )
+    }
 }
 
-/// # Compund Macros
-///
-/// These macros make it easy to operate on 8 or 16 bit registers but store them into other registers.
-///
-/// Perform an arithmetic instruction on a register without storing the result.
-macro_rules! perform_arithmetic {
-    // For a given register, operate on an 8 bit register to set flags, but do not return the result
-    ($register:ident, $self:ident.$fn:ident) => {
-        {
-            match $register {
-                ArithmeticTarget::A => operate_8bit_register!(a => $self.$fn),
-                ArithmeticTarget::B => operate_8bit_register!(b => $self.$fn),
-                ArithmeticTarget::C => operate_8bit_register!(c => $self.$fn),
-                ArithmeticTarget::D => operate_8bit_register!(d => $self.$fn),
-                ArithmeticTarget::E => operate_8bit_register!(e => $self.$fn),
-                ArithmeticTarget::H => operate_8bit_register!(h => $self.$fn),
-                ArithmeticTarget::L => operate_8bit_register!(l => $self.$fn),
-                ArithmeticTarget::HLI => todo!(),
-            }
-        }
-    };
-
-    // For a given register, perform 8 bit arithmetic from a given register and store it into the `a` register.
-    ($register:ident, $self:ident.$fn:ident => a) => {
-        {
-            match $register {
-                ArithmeticTarget::A => operate_8bit_register!(a => $self.$fn => a),
-                ArithmeticTarget::B => operate_8bit_register!(b => $self.$fn => a),
-                ArithmeticTarget::C => operate_8bit_register!(c => $self.$fn => a),
-                ArithmeticTarget::D => operate_8bit_register!(d => $self.$fn => a),
-                ArithmeticTarget::E => operate_8bit_register!(e => $self.$fn => a),
-                ArithmeticTarget::H => operate_8bit_register!(h => $self.$fn => a),
-                ArithmeticTarget::L => operate_8bit_register!(l => $self.$fn => a),
-                ArithmeticTarget::HLI => todo!(),
-            }
-        }
-    };
+impl std::error::Error for CPULockup {}
+
+/// What the CPU does when it locks up.
+pub enum LockupPolicy<B: Bus = Memory> {
+    // Stop executing instructions while time keeps passing, like the hardware does
+    FREEZE,
+    // `step` returns the lockup as an error
+    ERROR,
+    // Calls the hook once when the CPU locks up, then freezes
+    DEBUGGER(fn(&CPU<B>, &CPULockup)),
 }
 
-macro_rules! prefix_instruction {
-    ($target:ident, $self:ident.$fn:ident @ $bit_position:ident) => {
-        {
-            match $target {
-                PrefixTarget::A => operate_8bit_register!(a => $self.$fn @ $bit_position),
-                PrefixTarget::B => operate_8bit_register!(b => $self.$fn @ $bit_position),
-                PrefixTarget::C => operate_8bit_register!(c => $self.$fn @ $bit_position),
-                PrefixTarget::D => operate_8bit_register!(d => $self.$fn @ $bit_position),
-                PrefixTarget::E => operate_8bit_register!(e => $self.$fn @ $bit_position),
-                PrefixTarget::H => operate_8bit_register!(h => $self.$fn @ $bit_position),
-                PrefixTarget::L => operate_8bit_register!(l => $self.$fn @ $bit_position),
-                PrefixTarget::HLI => todo!(),
-            }
-        }
-    };
-
-    ($target:ident, ($self:ident.$fn:ident @ $bit_position:ident) => register) => {
-        {
-            match $target {
-                PrefixTarget::A => operate_8bit_register!(a => ($self.$fn @ $bit_position) => a),
-                PrefixTarget::B => operate_8bit_register!(b => ($self.$fn @ $bit_position) => b),
-                PrefixTarget::C => operate_8bit_register!(c => ($self.$fn @ $bit_position) => c),
-                PrefixTarget::D => operate_8bit_register!(d => ($self.$fn @ $bit_position) => d),
-                PrefixTarget::E => operate_8bit_register!(e => ($self.$fn @ $bit_position) => e),
-                PrefixTarget::H => operate_8bit_register!(h => ($self.$fn @ $bit_position) => h),
-                PrefixTarget::L => operate_8bit_register!(l => ($self.$fn @ $bit_position) => l),
-                PrefixTarget::HLI => todo!(),
-            }
-        }
-    };
-
-    ($target:ident, $self:ident.$fn:ident => register) => {
-        {
-            match $target {
-                PrefixTarget::A => operate_8bit_register!(a => $self.$fn => a),
-                PrefixTarget::B => operate_8bit_register!(b => $self.$fn => b),
-                PrefixTarget::C => operate_8bit_register!(c => $self.$fn => c),
-                PrefixTarget::D => operate_8bit_register!(d => $self.$fn => d),
-                PrefixTarget::E => operate_8bit_register!(e => $self.$fn => e),
-                PrefixTarget::H => operate_8bit_register!(h => $self.$fn => h),
-                PrefixTarget::L => operate_8bit_register!(l => $self.$fn => l),
-                PrefixTarget::HLI => todo!(),
-            }
-        }
-    };
+// Derived impls would require `B` to be `Copy`, which a function pointer doesn't need
+impl<B: Bus> Clone for LockupPolicy<B> {
+    fn clone(&self) -> Self {
+        *self
+    }
 }
 
-pub struct CPU {
+impl<B: Bus> Copy for LockupPolicy<B> {}
+
+pub struct CPU<B: Bus = Memory> {
     pub pc: u16,
     pub sp: u16,
+    // Interrupt master enable
+    pub ime: bool,
+    // Set by EI, `ime` is only enabled after the instruction following EI
+    ime_scheduled: bool,
+    pub state: CPUState,
+    // Set when HALT is executed with `ime` disabled and an interrupt pending, the next opcode is read twice
+    halt_bug: bool,
+    pub lockup_policy: LockupPolicy<B>,
+    // T-cycles spent by the instruction that is currently executing
+    cycles: u8,
     pub registers: Registers,
-    pub memory: Memory,
+    pub memory: B,
 }
 
-// CPU instruction functions
 impl CPU {
     pub fn new(boot_rom: Option<Vec<u8>>, rom: Vec<u8>) -> Self {
+        CPU::with_bus(Memory::new(boot_rom, rom))
+    }
+
+    /// Puts the CPU and IO registers in the state the boot rom of `model` leaves them in, so the game in rom can be
+    /// started at 0x0100 without running a boot rom.
+    pub fn skip_boot(&mut self, model: Model) {
+        // The DMG and MGB boot roms leave the half carry and carry flags set unless the header checksum is 0
+        let checksum_flags = if self.memory.read_byte(HEADER_CHECKSUM as u16) == 0 {
+            0x80
+        } else {
+            0xB0
+        };
+
+        let (af, bc, de, hl) = match model {
+            Model::DMG0 => (0x0100, 0xFF13, 0x00C1, 0x8403),
+            Model::DMG => (0x0100 | checksum_flags, 0x0013, 0x00D8, 0x014D),
+            Model::MGB => (0xFF00 | checksum_flags, 0x0013, 0x00D8, 0x014D),
+            Model::SGB => (0x0100, 0x0014, 0x0000, 0xC060),
+            Model::SGB2 => (0xFF00, 0x0014, 0x0000, 0xC060),
+            Model::CGB => (0x1180, 0x0000, 0xFF56, 0x000D),
+            Model::AGB => (0x1100, 0x0100, 0xFF56, 0x000D),
+        };
+
+        self.registers.set_af(af);
+        self.registers.set_bc(bc);
+        self.registers.set_de(de);
+        self.registers.set_hl(hl);
+        self.sp = 0xFFFE;
+        self.pc = 0x0100;
+        self.ime = false;
+        self.ime_scheduled = false;
+        self.halt_bug = false;
+        self.state = CPUState::RUNNING;
+
+        self.memory.skip_boot(model);
+    }
+}
+
+// CPU instruction functions
+impl<B: Bus> CPU<B> {
+    pub fn with_bus(memory: B) -> Self {
         CPU {
             pc: 0,
             sp: 0,
+            ime: false,
+            ime_scheduled: false,
+            state: CPUState::RUNNING,
+            halt_bug: false,
+            lockup_policy: LockupPolicy::FREEZE,
+            cycles: 0,
             registers: Registers::new(),
-            memory: Memory::new(boot_rom, rom),
+            memory,
+        }
+    }
+
+    /// Fetches the instruction at `pc`, executes it and returns the number of T-cycles it took.
+    ///
+    /// If interrupts are enabled and one is pending, it is dispatched instead of executing an instruction. While the
+    /// CPU is halted, stopped or locked up, nothing is executed and 4 cycles pass.
+    ///
+    /// Every memory access advances the rest of the system by one M-cycle at the moment it happens, so the returned
+    /// cycles have already been spent by the time `step` returns.
+    ///
+    /// Returns an error once the CPU has locked up if `lockup_policy` is `LockupPolicy::ERROR`.
+    pub fn step(&mut self) -> Result<u8, CPULockup> {
+        self.step_with(|cpu, opcode| DispatchTable::<B>::handler(opcode as usize)(cpu))
+    }
+
+    /// Same as `step`, but decodes the opcode into an `Instruction` and matches on it instead of going through the
+    /// dispatch table.
+    // Only the `--bench` mode uses it, to compare both ways of executing instructions
+    pub(crate) fn step_decoded(&mut self) -> Result<u8, CPULockup> {
+        self.step_with(|cpu, opcode| cpu.execute(Instruction::from_byte(opcode)))
+    }
+
+    #[inline(always)]
+    fn step_with(&mut self, execute: fn(&mut Self, u8)) -> Result<u8, CPULockup> {
+        self.cycles = 0;
+        self.step_instruction(execute);
+
+        match (self.state, self.lockup_policy) {
+            (CPUState::LOCKED(lockup), LockupPolicy::ERROR) => Err(lockup),
+            _ => Ok(self.cycles),
+        }
+    }
+
+    #[inline(always)]
+    fn step_instruction(&mut self, execute: fn(&mut Self, u8)) {
+        match self.state {
+            CPUState::RUNNING => {}
+            CPUState::HALTED => {
+                if self.pending_interrupt().is_none() {
+                    self.tick();
+                    return;
+                }
+
+                self.state = CPUState::RUNNING;
+            }
+            CPUState::STOPPED => {
+                if self.memory.read(INTERRUPT_FLAG as u16) & Interrupts::JOYPAD.mask() == 0 {
+                    self.tick();
+                    return;
+                }
+
+                self.state = CPUState::RUNNING;
+            }
+            CPUState::LOCKED(_) => {
+                self.tick();
+                return;
+            }
+        }
+
+        if self.dispatch_interrupt() {
+            return;
         }
+
+        let enable_ime = self.ime_scheduled;
+        let opcode = if self.halt_bug {
+            // The HALT bug: `pc` fails to increment after reading the opcode
+            self.halt_bug = false;
+            self.read_byte(self.pc)
+        } else {
+            self.read_next_byte()
+        };
+        execute(self, opcode);
+
+        // DI, or another EI, right after EI overrides the pending enable
+        if enable_ime && self.ime_scheduled {
+            self.ime = true;
+            self.ime_scheduled = false;
+        }
+    }
+
+    /// Whether the next `step` executes the instruction at `pc`, rather than idling or dispatching an interrupt.
+    pub fn will_execute_instruction(&self) -> bool {
+        let pending = self.pending_interrupt().is_some();
+        let awake = match self.state {
+            CPUState::RUNNING => true,
+            CPUState::HALTED => pending,
+            CPUState::STOPPED => {
+                self.memory.read(INTERRUPT_FLAG as u16) & Interrupts::JOYPAD.mask() != 0
+            }
+            CPUState::LOCKED(_) => false,
+        };
+
+        awake && !(self.ime && pending)
     }
 
-    pub fn execute_instruction(&mut self, instruction: Instruction) {
+    /// Requests an interrupt by setting its bit in IF, it is dispatched once IE and `ime` allow it.
+    // The timer and serial port set IF themselves, only the tests request interrupts from outside
+    #[cfg(test)]
+    pub fn request_interrupt(&mut self, interrupt: Interrupts) {
+        let flags = self.memory.read(INTERRUPT_FLAG as u16) | interrupt.mask();
+        self.memory.write(INTERRUPT_FLAG as u16, flags);
+    }
+
+    /// Returns the highest priority interrupt that is both requested in IF and enabled in IE.
+    fn pending_interrupt(&self) -> Option<Interrupts> {
+        let pending =
+            self.memory.read(INTERRUPT_FLAG as u16) & self.memory.read(INTERRUPT_ENABLE as u16);
+
+        Interrupts::PRIORITY
+            .into_iter()
+            .find(|interrupt| pending & interrupt.mask() != 0)
+    }
+
+    fn dispatch_interrupt(&mut self) -> bool {
+        if !self.ime {
+            return false;
+        }
+
+        let Some(interrupt) = self.pending_interrupt() else {
+            return false;
+        };
+        let flags = self.memory.read(INTERRUPT_FLAG as u16) & !interrupt.mask();
+        self.memory.write(INTERRUPT_FLAG as u16, flags);
+        self.ime = false;
+
+        // 2 wait cycles, pushing `pc` and jumping to the vector take 5 M-cycles in total
+        self.tick();
+        self.tick();
+        self.push(self.pc);
+        self.tick();
+        self.pc = u16::from(interrupt);
+
+        true
+    }
+
+    /// Executes a decoded instruction and returns the number of T-cycles it took, including the fetch of its opcode.
+    ///
+    /// `pc` is expected to point just past the opcode, any immediate operands are read from there.
+    #[cfg(test)]
+    pub fn execute_instruction(&mut self, instruction: Instruction) -> u8 {
+        self.cycles = 0;
+
+        // The opcode has already been fetched, but its M-cycle still counts. Prefixed instructions fetch 0xCB first.
+        self.tick();
+        if instruction.is_prefixed() {
+            self.tick();
+        }
+
+        self.execute(instruction);
+        self.cycles
+    }
+
+    // Inlined into every handler of the dispatch table, where `instruction` is a constant
+    #[inline(always)]
+    fn execute(&mut self, instruction: Instruction) {
         match instruction {
-            Instruction::ADD(register) => perform_arithmetic!(register, self.add => a),
-            Instruction::ADC(register) => perform_arithmetic!(register, self.adc => a),
-            Instruction::AND(register) => perform_arithmetic!(register, self.and => a),
+            Instruction::ADD(source) => self.accumulate(source, Self::add),
+            Instruction::ADC(source) => self.accumulate(source, Self::adc),
+            Instruction::AND(source) => self.accumulate(source, Self::and),
             Instruction::BIT(target, bit_position) => {
-                prefix_instruction!(target, self.bit @ bit_position);
+                // BIT only reads from (HL), it never writes a value back
+                let value = target.read(self);
+                self.bit(value, bit_position);
+            }
+            Instruction::CP(source) => {
+                let value = source.read(self);
+                self.compare(value);
             }
-            Instruction::CP(register) => perform_arithmetic!(register, self.compare),
-            Instruction::DEC(register) => match register {
-                IncDecTarget::A => operate_8bit_register!(a => self.dec => a),
-                IncDecTarget::B => operate_8bit_register!(b => self.dec => b),
-                IncDecTarget::C => operate_8bit_register!(c => self.dec => c),
-                IncDecTarget::D => operate_8bit_register!(d => self.dec => d),
-                IncDecTarget::E => operate_8bit_register!(e => self.dec => e),
-                IncDecTarget::H => operate_8bit_register!(h => self.dec => h),
-                IncDecTarget::L => operate_8bit_register!(l => self.dec => l),
-                IncDecTarget::BC => operate_16bit_register!(get_bc => self.dec_16bit => set_bc),
-                IncDecTarget::DE => operate_16bit_register!(get_de => self.dec_16bit => set_de),
-                IncDecTarget::HL => operate_16bit_register!(get_hl => self.dec_16bit => set_hl),
-                IncDecTarget::HLI => todo!(),
-                IncDecTarget::SP => todo!(),
+            Instruction::DEC(target) => match IncDecOperand::from(target) {
+                IncDecOperand::BYTE(target) => self.modify(target, Self::dec),
+                IncDecOperand::WORD(target) => self.modify_word(target, Self::dec_16bit),
             },
-            Instruction::INC(register) => match register {
-                IncDecTarget::A => operate_8bit_register!(a => self.inc => a),
-                IncDecTarget::B => operate_8bit_register!(b => self.inc => b),
-                IncDecTarget::C => operate_8bit_register!(c => self.inc => c),
-                IncDecTarget::D => operate_8bit_register!(d => self.inc => d),
-                IncDecTarget::E => operate_8bit_register!(e => self.inc => e),
-                IncDecTarget::H => operate_8bit_register!(h => self.inc => h),
-                IncDecTarget::L => operate_8bit_register!(l => self.inc => l),
-                IncDecTarget::BC => operate_16bit_register!(get_bc => self.inc_16bit => set_bc),
-                IncDecTarget::DE => operate_16bit_register!(get_de => self.inc_16bit => set_de),
-                IncDecTarget::HL => operate_16bit_register!(get_hl => self.inc_16bit => set_hl),
-                IncDecTarget::HLI => todo!(),
-                IncDecTarget::SP => todo!(),
+            Instruction::INC(target) => match IncDecOperand::from(target) {
+                IncDecOperand::BYTE(target) => self.modify(target, Self::inc),
+                IncDecOperand::WORD(target) => self.modify_word(target, Self::inc_16bit),
             },
-            Instruction::OR(register) => perform_arithmetic!(register, self.or => a),
-            Instruction::SBC(register) => perform_arithmetic!(register, self.sbc => a),
+            Instruction::OR(source) => self.accumulate(source, Self::or),
+            Instruction::SBC(source) => self.accumulate(source, Self::sbc),
+            Instruction::RES(target, bit_position) => {
+                self.modify(target, |cpu, value| cpu.reset(value, bit_position))
+            }
             Instruction::SET(target, bit_position) => {
-                prefix_instruction!(target, (self.set @ bit_position) => register);
+                self.modify(target, |cpu, value| cpu.set(value, bit_position))
             }
-
-            Instruction::SUB(register) => perform_arithmetic!(register, self.sub => a),
-            Instruction::XOR(register) => perform_arithmetic!(register, self.xor => a),
-            Instruction::ADDHL(register) => {
-                let value: u16 = match register {
-                    ADDHLTarget::BC => self.registers.get_bc(),
-                    ADDHLTarget::DE => self.registers.get_de(),
-                    ADDHLTarget::HL => self.registers.get_hl(),
-                    ADDHLTarget::SP => todo!(),
-                };
-
+            Instruction::SUB(source) => self.accumulate(source, Self::sub),
+            Instruction::XOR(source) => self.accumulate(source, Self::xor),
+            Instruction::ADDHL(source) => {
+                let value = source.read(self);
                 let result = self.add_hl(value);
                 self.registers.set_hl(result);
             }
             Instruction::CCF => self.ccf(),
-            Instruction::CPL => operate_8bit_register!(a => self.complement => a),
+            Instruction::CPL => self.registers.a = self.complement(self.registers.a),
             Instruction::SCF => self.scf(),
-            Instruction::SWAP(target) => prefix_instruction!(target, self.swap => register),
-            Instruction::RL(target) => prefix_instruction!(target, self.rl => register),
-            Instruction::RLA => operate_8bit_register!(a => self.rla => a),
-            Instruction::RLC(target) => prefix_instruction!(target, self.rlc => register),
-            Instruction::RLCA => operate_8bit_register!(a => self.rlca => a),
-            Instruction::RR(target) => prefix_instruction!(target, self.rr => register),
-            Instruction::RRA => operate_8bit_register!(a => self.rra => a),
-            Instruction::RRC(target) => prefix_instruction!(target, self.rrc => register),
-            Instruction::RRCA => operate_8bit_register!(a => self.rrca => a),
+            Instruction::SWAP(target) => self.modify(target, Self::swap),
+            Instruction::RL(target) => self.modify(target, Self::rl),
+            Instruction::RLA => self.registers.a = self.rla(self.registers.a),
+            Instruction::RLC(target) => self.modify(target, Self::rlc),
+            Instruction::RLCA => self.registers.a = self.rlca(self.registers.a),
+            Instruction::RR(target) => self.modify(target, Self::rr),
+            Instruction::RRA => self.registers.a = self.rra(self.registers.a),
+            Instruction::RRC(target) => self.modify(target, Self::rrc),
+            Instruction::RRCA => self.registers.a = self.rrca(self.registers.a),
+            Instruction::SLA(target) => self.modify(target, Self::sla),
+            Instruction::SRA(target) => self.modify(target, Self::sra),
+            Instruction::SRL(target) => self.modify(target, Self::srl),
             Instruction::LD(load_type) => match load_type {
                 LoadType::BYTE(target, source) => {
-                    let source_value = match source {
-                        LoadByteTarget::A => self.registers.a,
-                        LoadByteTarget::B => self.registers.b,
-                        LoadByteTarget::C => self.registers.c,
-                        LoadByteTarget::D => self.registers.d,
-                        LoadByteTarget::E => self.registers.e,
-                        LoadByteTarget::H => self.registers.h,
-                        LoadByteTarget::L => self.registers.l,
-                        LoadByteTarget::HLI => todo!(),
-                    };
-
-                    match target {
-                        LoadByteSource::A => self.registers.a = source_value,
-                        LoadByteSource::B => self.registers.b = source_value,
-                        LoadByteSource::C => self.registers.c = source_value,
-                        LoadByteSource::D => self.registers.d = source_value,
-                        LoadByteSource::E => self.registers.e = source_value,
-                        LoadByteSource::H => self.registers.h = source_value,
-                        LoadByteSource::L => self.registers.l = source_value,
-                        LoadByteSource::HLI => todo!(),
+                    let value = source.read(self);
+                    target.write(self, value);
+                }
+                LoadType::WORD(target, source) => {
+                    let value = source.read(self);
+
+                    // LD SP, HL and LD HL, SP+e8 spend an extra M-cycle on the 16 bit register transfer
+                    if let LoadWordSource::HL | LoadWordSource::SPE8 = source {
+                        self.tick();
                     }
-                } // LoadType::WORD(target) => match target {
-                  //     LoadWordTarget::BC => {
-                  //         operate_16bit_register!(get_bc => self.load_16bit => set_bc)
-                  //     }
-                  //     LoadWordTarget::DE => {
-                  //         operate_16bit_register!(get_de => self.load_16bit => set_de)
-                  //     }
-                  //     LoadWordTarget::HL => {
-                  //         operate_16bit_register!(get_hl => self.load_16bit => set_hl)
-                  //     }
-                  // },
+
+                    target.write(self, value);
+                }
+                LoadType::AFROMINDIRECT(source) => self.registers.a = source.read(self),
+                LoadType::INDIRECTFROMA(target) => target.write(self, self.registers.a),
             },
+            Instruction::ADDSP => {
+                let offset = self.read_next_byte();
+                self.sp = self.add_sp_signed(offset);
+
+                // The 16 bit addition is done in two internal M-cycles
+                self.tick();
+                self.tick();
+            }
+            Instruction::CALL(test) => {
+                let address = self.read_next_word();
+
+                if self.should_jump(test) {
+                    self.call(address);
+                }
+            }
+            Instruction::JP(test) => {
+                let address = self.read_next_word();
+
+                if self.should_jump(test) {
+                    self.tick();
+                    self.pc = address;
+                }
+            }
+            Instruction::JPHL => self.pc = self.registers.get_hl(),
+            Instruction::JR(test) => {
+                // The offset is a signed byte relative to the address of the next instruction
+                let offset = self.read_next_byte() as i8;
+
+                if self.should_jump(test) {
+                    self.tick();
+                    self.pc = self.pc.wrapping_add(offset as u16);
+                }
+            }
+            Instruction::RET(test) => {
+                // An unconditional return doesn't spend a cycle checking the flags
+                if test != JumpTest::ALWAYS {
+                    self.tick();
+                }
+
+                if self.should_jump(test) {
+                    self.pc = self.pop();
+                    self.tick();
+                }
+            }
+            Instruction::RETI => {
+                self.pc = self.pop();
+                self.tick();
+                self.ime = true;
+            }
+            Instruction::RST(vector) => self.call(u16::from(vector)),
+            Instruction::POP(target) => {
+                let value = self.pop();
+                target.write(self, value);
+            }
+            Instruction::PUSH(source) => {
+                let value = source.read(self);
+
+                // `sp` is decremented in an internal M-cycle before the first write
+                self.tick();
+                self.push(value);
+            }
+            Instruction::DAA => self.registers.a = self.daa(self.registers.a),
+            Instruction::DI => {
+                self.ime = false;
+                self.ime_scheduled = false;
+            }
+            Instruction::EI => self.ime_scheduled = true,
+            Instruction::HALT => {
+                if !self.ime && self.pending_interrupt().is_some() {
+                    self.halt_bug = true;
+                } else {
+                    self.state = CPUState::HALTED;
+                }
+            }
+            Instruction::NOP => {}
+            Instruction::PREFIX => {
+                let opcode = self.read_next_byte();
+                self.execute(Instruction::from_prefixed_byte(opcode));
+            }
+            Instruction::STOP => {
+                // STOP is followed by a byte that is skipped without being read
+                self.pc = self.pc.wrapping_add(1);
+                self.memory.write(DIVIDER as u16, 0);
+
+                if !self.memory.switch_speed() {
+                    self.state = CPUState::STOPPED;
+                }
+            }
+            Instruction::ILLEGAL(opcode) => self.lock_up(opcode),
+        }
+    }
+
+    /// Applies `op` to the `a` register and the value of `source`, storing the result in `a`.
+    fn accumulate<S: Source8>(&mut self, source: S, op: fn(&mut Self, u8) -> u8) {
+        let value = source.read(self);
+        self.registers.a = op(self, value);
+    }
+
+    /// Reads an 8 bit location, applies `op` to its value and writes the result back to it.
+    fn modify<T: Source8 + Target8>(&mut self, target: T, op: impl FnOnce(&mut Self, u8) -> u8) {
+        let value = target.read(self);
+        let result = op(self, value);
+        target.write(self, result);
+    }
+
+    /// Reads a 16 bit location, applies `op` to its value and writes the result back to it.
+    fn modify_word<T: Source16 + Target16>(&mut self, target: T, op: fn(&mut Self, u16) -> u16) {
+        let value = target.read(self);
+        let result = op(self, value);
+        target.write(self, result);
+    }
+
+    fn lock_up(&mut self, opcode: u8) {
+        // `pc` has already moved past the opcode
+        let pc = self.pc.wrapping_sub(1);
+        let lockup = CPULockup {
+            opcode,
+            pc,
+            bank: self.memory.rom_bank(pc),
+        };
+
+        self.state = CPUState::LOCKED(lockup);
+
+        if let LockupPolicy::DEBUGGER(hook) = self.lockup_policy {
+            hook(self, &lockup);
+        }
+    }
+
+    // Bus access, every read or write takes one M-cycle during which the rest of the system keeps running
+
+    fn tick(&mut self) {
+        self.cycles += 4;
+        self.memory.tick();
+    }
+
+    fn read_byte(&mut self, address: u16) -> u8 {
+        self.tick();
+        self.memory.read(address)
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8) {
+        self.tick();
+        self.memory.write(address, value);
+    }
+
+    // Instruction stream
+
+    fn read_next_byte(&mut self) -> u8 {
+        let value = self.read_byte(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+
+        value
+    }
+
+    fn read_next_word(&mut self) -> u16 {
+        let low = self.read_next_byte() as u16;
+        let high = self.read_next_byte() as u16;
+
+        (high << 8) | low
+    }
+
+    // Stack
+
+    fn push(&mut self, value: u16) {
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_byte(self.sp, (value >> 8) as u8);
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_byte(self.sp, (value & 0xFF) as u8);
+    }
+
+    fn pop(&mut self) -> u16 {
+        let low = self.read_byte(self.sp) as u16;
+        self.sp = self.sp.wrapping_add(1);
+        let high = self.read_byte(self.sp) as u16;
+        self.sp = self.sp.wrapping_add(1);
+
+        (high << 8) | low
+    }
+
+    // Jumps and Subroutines
+
+    /// Pushes the address of the next instruction onto the stack and jumps to `address`.
+    fn call(&mut self, address: u16) {
+        // `sp` is decremented in an internal M-cycle before the first write
+        self.tick();
+        self.push(self.pc);
+        self.pc = address;
+    }
+
+    fn should_jump(&self, test: JumpTest) -> bool {
+        match test {
+            JumpTest::NZ => !self.registers.f.zero,
+            JumpTest::Z => self.registers.f.zero,
+            JumpTest::NC => !self.registers.f.carry,
+            JumpTest::C => self.registers.f.carry,
+            JumpTest::ALWAYS => true,
         }
     }
 
@@ -390,6 +681,9 @@ impl CPU {
     // 16 bit instructions
 
     fn add_hl(&mut self, value: u16) -> u16 {
+        // The upper byte is added in an extra internal M-cycle
+        self.tick();
+
         let hl = self.registers.get_hl();
         let (new_value, did_overflow) = hl.overflowing_add(value);
         self.registers.f.subtract = false;
@@ -406,11 +700,32 @@ impl CPU {
         new_value
     }
 
+    fn add_sp_signed(&mut self, offset: u8) -> u16 {
+        // The offset is a signed byte, sign extending it lets a wrapping add subtract negative offsets
+        let new_value = self.sp.wrapping_add(offset as i8 as u16);
+        self.registers.f.zero = false;
+        self.registers.f.subtract = false;
+
+        // Both flags are computed as if this was an unsigned 8 bit add of the offset to the low byte of `sp`,
+        // no matter the sign of the offset.
+        // Example:
+        // sp = 0x00FF, offset = 0x01 (+1): 0xFF + 0x01 > 0xFF (half carry and carry set)
+        // sp = 0x0001, offset = 0xFF (-1): 0x01 + 0xFF > 0xFF (half carry and carry set)
+        self.registers.f.half_carry = (self.sp & 0xF) + (offset as u16 & 0xF) > 0xF;
+        self.registers.f.carry = (self.sp & 0xFF) + offset as u16 > 0xFF;
+
+        new_value
+    }
+
+    // 16 bit increments and decrements go through the address bus adder and take an extra M-cycle
+
     fn inc_16bit(&mut self, value: u16) -> u16 {
+        self.tick();
         value.wrapping_add(1)
     }
 
     fn dec_16bit(&mut self, value: u16) -> u16 {
+        self.tick();
         value.wrapping_sub(1)
     }
 
@@ -422,6 +737,10 @@ impl CPU {
         self.registers.f.half_carry = true;
     }
 
+    fn reset(&mut self, value: u8, bit_position: BitPosition) -> u8 {
+        value & !(1 << u8::from(bit_position))
+    }
+
     fn set(&mut self, value: u8, bit_position: BitPosition) -> u8 {
         value | 1 << u8::from(bit_position)
     }
@@ -440,8 +759,8 @@ impl CPU {
 
     fn rl(&mut self, value: u8) -> u8 {
         let carry = u8::from(self.registers.f.carry);
-        let highest_bit = (value & 0xFF) >> 7;
-        let new_value = ((value & 0xFF) << 1) | carry;
+        let highest_bit = value >> 7;
+        let new_value = (value << 1) | carry;
 
         self.registers.f.zero = new_value == 0;
         self.registers.f.subtract = false;
@@ -459,8 +778,8 @@ impl CPU {
     }
 
     fn rlc(&mut self, value: u8) -> u8 {
-        let highest_bit = (value & 0xFF) >> 7;
-        let new_value = ((value & 0xFF) << 1) | highest_bit;
+        let highest_bit = value >> 7;
+        let new_value = (value << 1) | highest_bit;
 
         self.registers.f.zero = new_value == 0;
         self.registers.f.subtract = false;
@@ -480,7 +799,7 @@ impl CPU {
     fn rr(&mut self, value: u8) -> u8 {
         let carry = u8::from(self.registers.f.carry);
         let lowest_bit = value & 0x1;
-        let new_value = (lowest_bit << 7) | (value >> 1);
+        let new_value = (carry << 7) | (value >> 1);
 
         self.registers.f.zero = new_value == 0;
         self.registers.f.subtract = false;
@@ -499,7 +818,7 @@ impl CPU {
 
     fn rrc(&mut self, value: u8) -> u8 {
         let lowest_bit = value & 0x1;
-        let new_value = value >> 1;
+        let new_value = (value >> 1) | (lowest_bit << 7);
 
         self.registers.f.zero = new_value == 0;
         self.registers.f.subtract = false;
@@ -516,6 +835,43 @@ impl CPU {
         new_value
     }
 
+    fn sla(&mut self, value: u8) -> u8 {
+        let highest_bit = value >> 7;
+        let new_value = value << 1;
+
+        self.registers.f.zero = new_value == 0;
+        self.registers.f.subtract = false;
+        self.registers.f.half_carry = false;
+        self.registers.f.carry = highest_bit == 1;
+
+        new_value
+    }
+
+    fn sra(&mut self, value: u8) -> u8 {
+        let lowest_bit = value & 0x1;
+        // Arithmetic shift, bit 7 keeps its value so the sign of the number is preserved
+        let new_value = (value & 0x80) | (value >> 1);
+
+        self.registers.f.zero = new_value == 0;
+        self.registers.f.subtract = false;
+        self.registers.f.half_carry = false;
+        self.registers.f.carry = lowest_bit == 1;
+
+        new_value
+    }
+
+    fn srl(&mut self, value: u8) -> u8 {
+        let lowest_bit = value & 0x1;
+        let new_value = value >> 1;
+
+        self.registers.f.zero = new_value == 0;
+        self.registers.f.subtract = false;
+        self.registers.f.half_carry = false;
+        self.registers.f.carry = lowest_bit == 1;
+
+        new_value
+    }
+
     // Misc instructions
 
     fn ccf(&mut self) {
@@ -524,6 +880,34 @@ impl CPU {
         self.registers.f.carry = !self.registers.f.carry;
     }
 
+    fn daa(&mut self, value: u8) -> u8 {
+        // DAA turns the result of adding or subtracting two BCD numbers back into BCD. The subtract, half carry and
+        // carry flags tell which digits overflowed (or underflowed) and need to be corrected by 6.
+        let mut correction = 0;
+        let mut carry = false;
+
+        if self.registers.f.half_carry || (!self.registers.f.subtract && (value & 0xF) > 0x9) {
+            correction |= 0x06;
+        }
+
+        if self.registers.f.carry || (!self.registers.f.subtract && value > 0x99) {
+            correction |= 0x60;
+            carry = true;
+        }
+
+        let new_value = if self.registers.f.subtract {
+            value.wrapping_sub(correction)
+        } else {
+            value.wrapping_add(correction)
+        };
+
+        self.registers.f.zero = new_value == 0;
+        self.registers.f.half_carry = false;
+        self.registers.f.carry = carry;
+
+        new_value
+    }
+
     fn complement(&mut self, value: u8) -> u8 {
         let new_value = !value;
         self.registers.f.subtract = true;
@@ -539,5 +923,14 @@ impl CPU {
     }
 }
 
+#[cfg(test)]
+#[path = "./tests/assembler.rs"]
+mod assembler;
+
+#[cfg(test)]
+#[path = "./tests/single_step_tests.rs"]
+mod single_step_tests;
+
+#[cfg(test)]
 #[path = "./tests/cpu_tests.rs"]
 mod tests;
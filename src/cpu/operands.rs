@@ -0,0 +1,486 @@
+//! # Operands
+//!
+//! Every operand of an instruction resolves to an 8 or 16 bit location: a register, the byte at (HL), an immediate
+//! value from the instruction stream or an absolute address. The target enums of the decoded instructions implement
+//! the traits below for the locations they can refer to, so an instruction is written once for all its addressing
+//! modes.
+//!
+//! Immediates can only be read, so the operands that can be written to are separate from the ones that can only be
+//! read. Reading or writing an operand can have side effects. Immediates advance `pc` and every memory access takes
+//! an M-cycle.
+
+use super::instructions::*;
+use super::CPU;
+use crate::bus::Bus;
+
+/// An 8 bit location an instruction can read from.
+pub trait Source8: Copy {
+    fn read<B: Bus>(self, cpu: &mut CPU<B>) -> u8;
+}
+
+/// An 8 bit location an instruction can write to.
+pub trait Target8: Copy {
+    fn write<B: Bus>(self, cpu: &mut CPU<B>, value: u8);
+}
+
+/// A 16 bit location an instruction can read from.
+pub trait Source16: Copy {
+    fn read<B: Bus>(self, cpu: &mut CPU<B>) -> u16;
+}
+
+/// A 16 bit location an instruction can write to.
+pub trait Target16: Copy {
+    fn write<B: Bus>(self, cpu: &mut CPU<B>, value: u16);
+}
+
+/// Every 8 bit location that can be read from and written to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Location8 {
+    A,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    // The byte at the address in HL
+    HLI,
+    INDIRECT(Indirect),
+}
+
+impl Source8 for Location8 {
+    fn read<B: Bus>(self, cpu: &mut CPU<B>) -> u8 {
+        match self {
+            Location8::A => cpu.registers.a,
+            Location8::B => cpu.registers.b,
+            Location8::C => cpu.registers.c,
+            Location8::D => cpu.registers.d,
+            Location8::E => cpu.registers.e,
+            Location8::H => cpu.registers.h,
+            Location8::L => cpu.registers.l,
+            Location8::HLI => cpu.read_byte(cpu.registers.get_hl()),
+            Location8::INDIRECT(indirect) => {
+                let address = indirect.address(cpu);
+                cpu.read_byte(address)
+            }
+        }
+    }
+}
+
+impl Target8 for Location8 {
+    fn write<B: Bus>(self, cpu: &mut CPU<B>, value: u8) {
+        match self {
+            Location8::A => cpu.registers.a = value,
+            Location8::B => cpu.registers.b = value,
+            Location8::C => cpu.registers.c = value,
+            Location8::D => cpu.registers.d = value,
+            Location8::E => cpu.registers.e = value,
+            Location8::H => cpu.registers.h = value,
+            Location8::L => cpu.registers.l = value,
+            Location8::HLI => cpu.write_byte(cpu.registers.get_hl(), value),
+            Location8::INDIRECT(indirect) => {
+                let address = indirect.address(cpu);
+                cpu.write_byte(address, value);
+            }
+        }
+    }
+}
+
+/// Every 8 bit value an instruction can read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operand8 {
+    LOCATION(Location8),
+    // Immediate byte
+    D8,
+}
+
+impl Source8 for Operand8 {
+    fn read<B: Bus>(self, cpu: &mut CPU<B>) -> u8 {
+        match self {
+            Operand8::LOCATION(location) => location.read(cpu),
+            Operand8::D8 => cpu.read_next_byte(),
+        }
+    }
+}
+
+/// Every 16 bit register pair, they can be read from and written to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Register16 {
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+}
+
+impl Source16 for Register16 {
+    fn read<B: Bus>(self, cpu: &mut CPU<B>) -> u16 {
+        match self {
+            Register16::AF => cpu.registers.get_af(),
+            Register16::BC => cpu.registers.get_bc(),
+            Register16::DE => cpu.registers.get_de(),
+            Register16::HL => cpu.registers.get_hl(),
+            Register16::SP => cpu.sp,
+        }
+    }
+}
+
+impl Target16 for Register16 {
+    fn write<B: Bus>(self, cpu: &mut CPU<B>, value: u16) {
+        match self {
+            Register16::AF => cpu.registers.set_af(value),
+            Register16::BC => cpu.registers.set_bc(value),
+            Register16::DE => cpu.registers.set_de(value),
+            Register16::HL => cpu.registers.set_hl(value),
+            Register16::SP => cpu.sp = value,
+        }
+    }
+}
+
+/// Every 16 bit value an instruction can read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operand16 {
+    REGISTER(Register16),
+    // Immediate word
+    D16,
+    // SP plus an immediate signed byte
+    SPE8,
+}
+
+impl Source16 for Operand16 {
+    fn read<B: Bus>(self, cpu: &mut CPU<B>) -> u16 {
+        match self {
+            Operand16::REGISTER(register) => register.read(cpu),
+            Operand16::D16 => cpu.read_next_word(),
+            Operand16::SPE8 => {
+                let offset = cpu.read_next_byte();
+                cpu.add_sp_signed(offset)
+            }
+        }
+    }
+}
+
+/// Every 16 bit location an instruction can write to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Location16 {
+    REGISTER(Register16),
+    // The word at an immediate address
+    A16,
+}
+
+impl Target16 for Location16 {
+    fn write<B: Bus>(self, cpu: &mut CPU<B>, value: u16) {
+        match self {
+            Location16::REGISTER(register) => register.write(cpu, value),
+            Location16::A16 => {
+                let address = cpu.read_next_word();
+                cpu.write_byte(address, (value & 0xFF) as u8);
+                cpu.write_byte(address.wrapping_add(1), (value >> 8) as u8);
+            }
+        }
+    }
+}
+
+impl Indirect {
+    /// Resolves the address an indirect load reads from or writes to, reading any immediate operand and applying the
+    /// post increment/decrement of `hl`.
+    fn address<B: Bus>(self, cpu: &mut CPU<B>) -> u16 {
+        match self {
+            Indirect::BC => cpu.registers.get_bc(),
+            Indirect::DE => cpu.registers.get_de(),
+            Indirect::HLINC => {
+                let hl = cpu.registers.get_hl();
+                cpu.registers.set_hl(hl.wrapping_add(1));
+                hl
+            }
+            Indirect::HLDEC => {
+                let hl = cpu.registers.get_hl();
+                cpu.registers.set_hl(hl.wrapping_sub(1));
+                hl
+            }
+            Indirect::HIGHC => 0xFF00 | cpu.registers.c as u16,
+            Indirect::HIGHA8 => 0xFF00 | cpu.read_next_byte() as u16,
+            Indirect::A16 => cpu.read_next_word(),
+        }
+    }
+}
+
+/// The location an INC or DEC instruction operates on, they work on both 8 bit locations and register pairs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IncDecOperand {
+    BYTE(Location8),
+    WORD(Register16),
+}
+
+impl std::convert::From<IncDecTarget> for IncDecOperand {
+    fn from(target: IncDecTarget) -> Self {
+        match target {
+            IncDecTarget::A => IncDecOperand::BYTE(Location8::A),
+            IncDecTarget::B => IncDecOperand::BYTE(Location8::B),
+            IncDecTarget::C => IncDecOperand::BYTE(Location8::C),
+            IncDecTarget::D => IncDecOperand::BYTE(Location8::D),
+            IncDecTarget::E => IncDecOperand::BYTE(Location8::E),
+            IncDecTarget::H => IncDecOperand::BYTE(Location8::H),
+            IncDecTarget::L => IncDecOperand::BYTE(Location8::L),
+            IncDecTarget::HLI => IncDecOperand::BYTE(Location8::HLI),
+            IncDecTarget::BC => IncDecOperand::WORD(Register16::BC),
+            IncDecTarget::DE => IncDecOperand::WORD(Register16::DE),
+            IncDecTarget::HL => IncDecOperand::WORD(Register16::HL),
+            IncDecTarget::SP => IncDecOperand::WORD(Register16::SP),
+        }
+    }
+}
+
+// 8 bit operands
+
+impl std::convert::From<ArithmeticTarget> for Operand8 {
+    fn from(target: ArithmeticTarget) -> Self {
+        match target {
+            ArithmeticTarget::A => Operand8::LOCATION(Location8::A),
+            ArithmeticTarget::B => Operand8::LOCATION(Location8::B),
+            ArithmeticTarget::C => Operand8::LOCATION(Location8::C),
+            ArithmeticTarget::D => Operand8::LOCATION(Location8::D),
+            ArithmeticTarget::E => Operand8::LOCATION(Location8::E),
+            ArithmeticTarget::H => Operand8::LOCATION(Location8::H),
+            ArithmeticTarget::L => Operand8::LOCATION(Location8::L),
+            ArithmeticTarget::HLI => Operand8::LOCATION(Location8::HLI),
+            ArithmeticTarget::D8 => Operand8::D8,
+        }
+    }
+}
+
+impl std::convert::From<PrefixTarget> for Location8 {
+    fn from(target: PrefixTarget) -> Self {
+        match target {
+            PrefixTarget::A => Location8::A,
+            PrefixTarget::B => Location8::B,
+            PrefixTarget::C => Location8::C,
+            PrefixTarget::D => Location8::D,
+            PrefixTarget::E => Location8::E,
+            PrefixTarget::H => Location8::H,
+            PrefixTarget::L => Location8::L,
+            PrefixTarget::HLI => Location8::HLI,
+        }
+    }
+}
+
+impl std::convert::From<LoadByteSource> for Operand8 {
+    fn from(source: LoadByteSource) -> Self {
+        match source {
+            LoadByteSource::A => Operand8::LOCATION(Location8::A),
+            LoadByteSource::B => Operand8::LOCATION(Location8::B),
+            LoadByteSource::C => Operand8::LOCATION(Location8::C),
+            LoadByteSource::D => Operand8::LOCATION(Location8::D),
+            LoadByteSource::E => Operand8::LOCATION(Location8::E),
+            LoadByteSource::H => Operand8::LOCATION(Location8::H),
+            LoadByteSource::L => Operand8::LOCATION(Location8::L),
+            LoadByteSource::HLI => Operand8::LOCATION(Location8::HLI),
+            LoadByteSource::D8 => Operand8::D8,
+        }
+    }
+}
+
+impl std::convert::From<LoadByteTarget> for Location8 {
+    fn from(target: LoadByteTarget) -> Self {
+        match target {
+            LoadByteTarget::A => Location8::A,
+            LoadByteTarget::B => Location8::B,
+            LoadByteTarget::C => Location8::C,
+            LoadByteTarget::D => Location8::D,
+            LoadByteTarget::E => Location8::E,
+            LoadByteTarget::H => Location8::H,
+            LoadByteTarget::L => Location8::L,
+            LoadByteTarget::HLI => Location8::HLI,
+        }
+    }
+}
+
+impl Source8 for ArithmeticTarget {
+    fn read<B: Bus>(self, cpu: &mut CPU<B>) -> u8 {
+        Operand8::from(self).read(cpu)
+    }
+}
+
+impl Source8 for PrefixTarget {
+    fn read<B: Bus>(self, cpu: &mut CPU<B>) -> u8 {
+        Location8::from(self).read(cpu)
+    }
+}
+
+impl Target8 for PrefixTarget {
+    fn write<B: Bus>(self, cpu: &mut CPU<B>, value: u8) {
+        Location8::from(self).write(cpu, value)
+    }
+}
+
+impl Source8 for LoadByteSource {
+    fn read<B: Bus>(self, cpu: &mut CPU<B>) -> u8 {
+        Operand8::from(self).read(cpu)
+    }
+}
+
+impl Target8 for LoadByteTarget {
+    fn write<B: Bus>(self, cpu: &mut CPU<B>, value: u8) {
+        Location8::from(self).write(cpu, value)
+    }
+}
+
+impl Source8 for Indirect {
+    fn read<B: Bus>(self, cpu: &mut CPU<B>) -> u8 {
+        Location8::INDIRECT(self).read(cpu)
+    }
+}
+
+impl Target8 for Indirect {
+    fn write<B: Bus>(self, cpu: &mut CPU<B>, value: u8) {
+        Location8::INDIRECT(self).write(cpu, value)
+    }
+}
+
+// 16 bit operands
+
+impl std::convert::From<ADDHLTarget> for Register16 {
+    fn from(target: ADDHLTarget) -> Self {
+        match target {
+            ADDHLTarget::BC => Register16::BC,
+            ADDHLTarget::DE => Register16::DE,
+            ADDHLTarget::HL => Register16::HL,
+            ADDHLTarget::SP => Register16::SP,
+        }
+    }
+}
+
+impl std::convert::From<LoadWordSource> for Operand16 {
+    fn from(source: LoadWordSource) -> Self {
+        match source {
+            LoadWordSource::D16 => Operand16::D16,
+            LoadWordSource::HL => Operand16::REGISTER(Register16::HL),
+            LoadWordSource::SP => Operand16::REGISTER(Register16::SP),
+            LoadWordSource::SPE8 => Operand16::SPE8,
+        }
+    }
+}
+
+impl std::convert::From<LoadWordTarget> for Location16 {
+    fn from(target: LoadWordTarget) -> Self {
+        match target {
+            LoadWordTarget::BC => Location16::REGISTER(Register16::BC),
+            LoadWordTarget::DE => Location16::REGISTER(Register16::DE),
+            LoadWordTarget::HL => Location16::REGISTER(Register16::HL),
+            LoadWordTarget::SP => Location16::REGISTER(Register16::SP),
+            LoadWordTarget::A16 => Location16::A16,
+        }
+    }
+}
+
+impl std::convert::From<StackTarget> for Register16 {
+    fn from(target: StackTarget) -> Self {
+        match target {
+            StackTarget::BC => Register16::BC,
+            StackTarget::DE => Register16::DE,
+            StackTarget::HL => Register16::HL,
+            StackTarget::AF => Register16::AF,
+        }
+    }
+}
+
+impl Source16 for ADDHLTarget {
+    fn read<B: Bus>(self, cpu: &mut CPU<B>) -> u16 {
+        Register16::from(self).read(cpu)
+    }
+}
+
+impl Source16 for LoadWordSource {
+    fn read<B: Bus>(self, cpu: &mut CPU<B>) -> u16 {
+        Operand16::from(self).read(cpu)
+    }
+}
+
+impl Target16 for LoadWordTarget {
+    fn write<B: Bus>(self, cpu: &mut CPU<B>, value: u16) {
+        Location16::from(self).write(cpu, value)
+    }
+}
+
+impl Source16 for StackTarget {
+    fn read<B: Bus>(self, cpu: &mut CPU<B>) -> u16 {
+        Register16::from(self).read(cpu)
+    }
+}
+
+impl Target16 for StackTarget {
+    fn write<B: Bus>(self, cpu: &mut CPU<B>, value: u16) {
+        Register16::from(self).write(cpu, value)
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use crate::bus::FlatRam;
+
+    fn setup() -> CPU<FlatRam> {
+        let mut memory = FlatRam::new();
+        memory.load(0x0000, &[0x42, 0x00, 0xC1]);
+
+        CPU::with_bus(memory)
+    }
+
+    #[test]
+    fn reads_and_writes_8bit_operands() {
+        let mut cpu = setup();
+        cpu.registers.set_hl(0xC000);
+
+        PrefixTarget::HLI.write(&mut cpu, 0x12);
+        LoadByteTarget::B.write(&mut cpu, 0x34);
+
+        assert_eq!(cpu.memory.read(0xC000), 0x12);
+        assert_eq!(ArithmeticTarget::HLI.read(&mut cpu), 0x12);
+        assert_eq!(LoadByteSource::B.read(&mut cpu), 0x34);
+
+        // Immediates are read from the instruction stream
+        assert_eq!(ArithmeticTarget::D8.read(&mut cpu), 0x42);
+        assert_eq!(cpu.pc, 0x1);
+    }
+
+    #[test]
+    fn indirect_operands_resolve_their_address() {
+        let mut cpu = setup();
+        cpu.registers.set_hl(0xC000);
+        cpu.memory.write(0xC001, 0x78);
+
+        Indirect::HLINC.write(&mut cpu, 0x56);
+
+        assert_eq!(cpu.memory.read(0xC000), 0x56);
+        assert_eq!(cpu.registers.get_hl(), 0xC001);
+        assert_eq!(Indirect::HLDEC.read(&mut cpu), 0x78);
+        assert_eq!(cpu.registers.get_hl(), 0xC000);
+    }
+
+    #[test]
+    fn reads_and_writes_16bit_operands() {
+        let mut cpu = setup();
+        cpu.pc = 0x1;
+
+        // The address 0xC100 is the immediate word at `pc`
+        LoadWordTarget::A16.write(&mut cpu, 0xBEEF);
+        StackTarget::AF.write(&mut cpu, 0x12FF);
+
+        assert_eq!(cpu.memory.read(0xC100), 0xEF);
+        assert_eq!(cpu.memory.read(0xC101), 0xBE);
+        assert_eq!(StackTarget::AF.read(&mut cpu), 0x12F0);
+        assert_eq!(cpu.pc, 0x3);
+    }
+
+    #[test]
+    fn inc_dec_targets_split_by_width() {
+        assert_eq!(
+            IncDecOperand::from(IncDecTarget::HLI),
+            IncDecOperand::BYTE(Location8::HLI)
+        );
+        assert_eq!(
+            IncDecOperand::from(IncDecTarget::SP),
+            IncDecOperand::WORD(Register16::SP)
+        );
+    }
+}
@@ -33,6 +33,16 @@ impl Registers {
         }
     }
 
+    pub fn get_af(&self) -> u16 {
+        (self.a as u16) << 8 | u8::from(self.f) as u16
+    }
+
+    pub fn set_af(&mut self, value: u16) {
+        self.a = ((value & 0xFF00) >> 8) as u8;
+        // Only the upper nibble of `f` holds flags, the lower nibble always reads back as 0
+        self.f = FlagsRegister::from((value & 0xFF) as u8);
+    }
+
     pub fn get_bc(&self) -> u16 {
         (self.b as u16) << 8 | self.c as u16
     }
@@ -61,6 +71,7 @@ impl Registers {
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct FlagsRegister {
     pub zero: bool,
     pub subtract: bool,
@@ -104,6 +115,7 @@ mod unit_tests {
     #[test]
     fn get_16_bit_registers() {
         let registers = setup();
+        assert_eq!(registers.get_af(), 0);
         assert_eq!(registers.get_bc(), 0);
         assert_eq!(registers.get_de(), 0);
         assert_eq!(registers.get_hl(), 0);
@@ -129,4 +141,18 @@ mod unit_tests {
         assert_eq!(registers.h, 0x0A);
         assert_eq!(registers.l, 0x0B);
     }
+
+    #[test]
+    fn set_af_register() {
+        let mut registers = setup();
+        registers.set_af(0x12FF);
+
+        // the lower nibble of the flags register can't be set
+        assert_eq!(registers.get_af(), 0x12F0);
+        assert_eq!(registers.a, 0x12);
+        assert!(registers.f.zero);
+        assert!(registers.f.subtract);
+        assert!(registers.f.half_carry);
+        assert!(registers.f.carry);
+    }
 }
@@ -0,0 +1,458 @@
+//! # Assembler
+//!
+//! A small SM83 assembler for writing CPU tests as source code. It understands the RGBDS syntax the disassembler
+//! writes, labels (`loop:`), `db`/`dw` directives and `;` comments.
+//!
+//! Instead of its own table of mnemonics, every instruction is matched against the disassembly of all opcodes with
+//! placeholders for their immediate operands (`ld a, n8`, `jr nz, e8`), so both always agree on the syntax.
+
+use super::instructions::Instruction;
+use std::collections::HashMap;
+
+/// Assembles `source` into the bytes of a program that starts at `origin`.
+pub fn assemble(source: &str, origin: u16) -> Result<Vec<u8>, String> {
+    let encodings = encodings();
+    let mut statements = Vec::new();
+    let mut labels = HashMap::new();
+    let mut address = origin;
+
+    // The first pass finds the address of every label, the size of a statement never depends on a label
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let mut line = normalize(line);
+
+        if let Some((label, rest)) = line.split_once(':') {
+            if !is_identifier(label) {
+                return Err(format!("Line {}: invalid label `{}`", line_number, label));
+            }
+
+            if labels.insert(label.to_string(), address).is_some() {
+                return Err(format!("Line {}: duplicate label `{}`", line_number, label));
+            }
+
+            line = rest.trim().to_string();
+        }
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let statement = parse_statement(&line, &encodings)
+            .ok_or_else(|| format!("Line {}: cannot assemble `{}`", line_number, line))?;
+        let size = statement.size();
+
+        statements.push((line_number, address, statement));
+        address = address.wrapping_add(size);
+    }
+
+    let mut bytes = Vec::new();
+
+    for (line_number, address, statement) in statements {
+        let encoded = statement
+            .encode(address, &labels)
+            .map_err(|error| format!("Line {}: {}", line_number, error))?;
+
+        bytes.extend(encoded);
+    }
+
+    Ok(bytes)
+}
+
+enum Statement {
+    // `opcode` includes the 0xCB prefix byte of prefixed instructions
+    INSTRUCTION {
+        opcode: Vec<u8>,
+        operand: Option<(Immediate, String)>,
+    },
+    BYTES(Vec<String>),
+    WORDS(Vec<String>),
+}
+
+/// The kind of immediate operand an instruction takes, named after their placeholders.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Immediate {
+    N8,
+    N16,
+    A8,
+    A16,
+    E8,
+    // The e8 operand of JR is written as the address it jumps to
+    JR,
+}
+
+impl Immediate {
+    fn size(self) -> u16 {
+        match self {
+            Immediate::N16 | Immediate::A16 => 2,
+            _ => 1,
+        }
+    }
+}
+
+impl Statement {
+    fn size(&self) -> u16 {
+        match self {
+            Statement::INSTRUCTION { opcode, operand } => {
+                let operand_size = match operand {
+                    Some((immediate, _)) => immediate.size(),
+                    None => 0,
+                };
+
+                // STOP is followed by a padding byte
+                let padding = if opcode[..] == [0x10] { 1 } else { 0 };
+
+                opcode.len() as u16 + operand_size + padding
+            }
+            Statement::BYTES(values) => values.len() as u16,
+            Statement::WORDS(values) => values.len() as u16 * 2,
+        }
+    }
+
+    fn encode(&self, address: u16, labels: &HashMap<String, u16>) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+
+        match self {
+            Statement::INSTRUCTION { opcode, operand } => {
+                bytes.extend(opcode);
+
+                if opcode[..] == [0x10] {
+                    bytes.push(0x00);
+                }
+
+                if let Some((immediate, expression)) = operand {
+                    let value = evaluate(expression, labels)?;
+
+                    match immediate {
+                        Immediate::N16 | Immediate::A16 => {
+                            bytes.extend(to_word(value, expression)?.to_le_bytes())
+                        }
+                        Immediate::JR => {
+                            let next_address = address.wrapping_add(self.size()) as i32;
+                            let offset = value - next_address;
+
+                            if !(-128..=127).contains(&offset) {
+                                return Err(format!("jump to `{}` is out of range", expression));
+                            }
+
+                            bytes.push(offset as u8);
+                        }
+                        // 0xFF00 page addresses can be written in full or as their low byte
+                        Immediate::A8 if (0xFF00..=0xFFFF).contains(&value) => {
+                            bytes.push(value as u8)
+                        }
+                        Immediate::N8 | Immediate::A8 | Immediate::E8 => {
+                            bytes.push(to_byte(value, expression)?)
+                        }
+                    }
+                }
+            }
+            Statement::BYTES(values) => {
+                for value in values {
+                    bytes.push(to_byte(evaluate(value, labels)?, value)?);
+                }
+            }
+            Statement::WORDS(values) => {
+                for value in values {
+                    bytes.extend(to_word(evaluate(value, labels)?, value)?.to_le_bytes());
+                }
+            }
+        }
+
+        Ok(bytes)
+    }
+}
+
+/// The opcode and syntax of every instruction, e.g. `ld a, n8` for `[0x3E]`.
+fn encodings() -> Vec<(Vec<u8>, String)> {
+    let unprefixed = (0..=0xFF).filter_map(|opcode| match Instruction::from_byte(opcode) {
+        Instruction::PREFIX | Instruction::ILLEGAL(_) => None,
+        instruction => Some((vec![opcode], instruction.to_string())),
+    });
+    let prefixed = (0..=0xFF).map(|opcode| {
+        let instruction = Instruction::from_prefixed_byte(opcode);
+        (vec![0xCB, opcode], instruction.to_string())
+    });
+
+    unprefixed.chain(prefixed).collect()
+}
+
+fn parse_statement(line: &str, encodings: &[(Vec<u8>, String)]) -> Option<Statement> {
+    if let Some(values) = line.strip_prefix("db ") {
+        return Some(Statement::BYTES(split_values(values)));
+    }
+
+    if let Some(values) = line.strip_prefix("dw ") {
+        return Some(Statement::WORDS(split_values(values)));
+    }
+
+    parse_instruction(line, encodings).or_else(|| {
+        // `cp b` is shorthand for `cp a, b`, the same goes for the other 8 bit arithmetic instructions
+        let (mnemonic, operand) = line.split_once(' ')?;
+
+        match mnemonic {
+            "add" | "adc" | "sub" | "sbc" | "and" | "xor" | "or" | "cp" => {
+                parse_instruction(&format!("{} a, {}", mnemonic, operand), encodings)
+            }
+            _ => None,
+        }
+    })
+}
+
+fn parse_instruction(line: &str, encodings: &[(Vec<u8>, String)]) -> Option<Statement> {
+    // Instructions without an immediate operand have to match exactly, so `ld a, b` is never mistaken for `ld a, n8`
+    // with a label called `b`
+    if let Some((opcode, _)) = encodings.iter().find(|(_, syntax)| syntax == line) {
+        return Some(Statement::INSTRUCTION {
+            opcode: opcode.clone(),
+            operand: None,
+        });
+    }
+
+    for (opcode, syntax) in encodings {
+        let Some((immediate, prefix, suffix)) = split_placeholder(syntax, opcode) else {
+            continue;
+        };
+
+        let expression = line
+            .strip_prefix(prefix)
+            .and_then(|rest| rest.strip_suffix(suffix));
+
+        if let Some(expression) = expression {
+            if is_expression(expression) {
+                return Some(Statement::INSTRUCTION {
+                    opcode: opcode.clone(),
+                    operand: Some((immediate, expression.to_string())),
+                });
+            }
+        }
+    }
+
+    None
+}
+
+/// Splits `syntax` around its immediate operand placeholder.
+fn split_placeholder<'a>(syntax: &'a str, opcode: &[u8]) -> Option<(Immediate, &'a str, &'a str)> {
+    let placeholders = [
+        ("n16", Immediate::N16),
+        ("a16", Immediate::A16),
+        ("n8", Immediate::N8),
+        ("a8", Immediate::A8),
+        ("e8", Immediate::E8),
+    ];
+
+    placeholders.iter().find_map(|(placeholder, immediate)| {
+        let start = syntax.find(placeholder)?;
+        let immediate = match (immediate, opcode[0]) {
+            (Immediate::E8, 0x18 | 0x20 | 0x28 | 0x30 | 0x38) => Immediate::JR,
+            _ => *immediate,
+        };
+
+        Some((
+            immediate,
+            &syntax[..start],
+            &syntax[start + placeholder.len()..],
+        ))
+    })
+}
+
+/// Lower cases the line and removes comments and extra whitespace, so `LD  A,[HL+] ; load` becomes `ld a, [hl+]`.
+fn normalize(line: &str) -> String {
+    let line = match line.split_once(';') {
+        Some((code, _)) => code,
+        None => line,
+    };
+
+    let line = line
+        .to_lowercase()
+        .replace(',', ", ")
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .replace(" ,", ",")
+        .replace("[ ", "[")
+        .replace(" ]", "]");
+
+    // Negative offsets of `ld hl, sp+e8` are written as `sp-3`
+    line.replace("sp-", "sp+-")
+        .replace("sp + ", "sp+")
+        .replace("sp +", "sp+")
+}
+
+fn split_values(values: &str) -> Vec<String> {
+    values
+        .split(',')
+        .map(|value| value.trim().to_string())
+        .collect()
+}
+
+/// Whether `expression` is a number or a label, register names are not valid labels.
+fn is_expression(expression: &str) -> bool {
+    const REGISTERS: [&str; 12] = [
+        "a", "b", "c", "d", "e", "h", "l", "af", "bc", "de", "hl", "sp",
+    ];
+
+    let magnitude = expression.strip_prefix('-').unwrap_or(expression);
+    let is_number = magnitude
+        .starts_with(|character: char| "$%".contains(character) || character.is_ascii_digit())
+        && magnitude[1..]
+            .chars()
+            .all(|character| character.is_ascii_alphanumeric());
+
+    is_number || (is_identifier(magnitude) && !REGISTERS.contains(&magnitude))
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut characters = name.chars();
+
+    matches!(characters.next(), Some(first) if first.is_ascii_alphabetic() || first == '_')
+        && characters.all(|character| character.is_ascii_alphanumeric() || character == '_')
+}
+
+/// Evaluates a number (`42`, `$2A`, `0x2A`, `%101010`, `-3`) or a label.
+fn evaluate(expression: &str, labels: &HashMap<String, u16>) -> Result<i32, String> {
+    let (negative, magnitude) = match expression.strip_prefix('-') {
+        Some(magnitude) => (true, magnitude),
+        None => (false, expression),
+    };
+
+    let value = if let Some(hex) = magnitude
+        .strip_prefix('$')
+        .or_else(|| magnitude.strip_prefix("0x"))
+    {
+        i32::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = magnitude.strip_prefix('%') {
+        i32::from_str_radix(binary, 2).ok()
+    } else if magnitude.starts_with(|character: char| character.is_ascii_digit()) {
+        magnitude.parse().ok()
+    } else {
+        labels.get(magnitude).map(|&address| address as i32)
+    };
+
+    match value {
+        Some(value) if negative => Ok(-value),
+        Some(value) => Ok(value),
+        None => Err(format!("cannot evaluate `{}`", expression)),
+    }
+}
+
+/// Truncates `value` to a byte, negative values are stored as two's complement.
+fn to_byte(value: i32, expression: &str) -> Result<u8, String> {
+    if !(-128..=255).contains(&value) {
+        return Err(format!("`{}` does not fit in a byte", expression));
+    }
+
+    Ok(value as u8)
+}
+
+/// Truncates `value` to a word, negative values are stored as two's complement.
+fn to_word(value: i32, expression: &str) -> Result<u16, String> {
+    if !(-32768..=65535).contains(&value) {
+        return Err(format!("`{}` does not fit in a word", expression));
+    }
+
+    Ok(value as u16)
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use crate::bus::FlatRam;
+    use crate::cpu::disassembler::disassemble;
+
+    #[test]
+    fn assembles_instructions() {
+        let source = "
+            ld a, [hl+]
+            LD B,$42       ; upper case works too
+            ldh [$FF44], a
+            ld hl, sp-2
+            cp 10
+            bit 7, [hl]
+            stop
+        ";
+
+        assert_eq!(
+            assemble(source, 0x0100),
+            Ok(vec![
+                0x2A, 0x06, 0x42, 0xE0, 0x44, 0xF8, 0xFE, 0xFE, 0x0A, 0xCB, 0x7E, 0x10, 0x00
+            ])
+        );
+    }
+
+    #[test]
+    fn resolves_labels() {
+        let source = "
+            start:
+                jr forward
+                dw start, data
+            forward: jp start
+            data:
+                db 1, %10, $FF
+        ";
+
+        assert_eq!(
+            assemble(source, 0x0100),
+            Ok(vec![
+                0x18, 0x04, 0x00, 0x01, 0x09, 0x01, 0xC3, 0x00, 0x01, 0x01, 0x02, 0xFF
+            ])
+        );
+    }
+
+    #[test]
+    fn reports_errors() {
+        assert_eq!(
+            assemble("nop\nld a, [sp]", 0),
+            Err("Line 2: cannot assemble `ld a, [sp]`".to_string())
+        );
+        assert_eq!(
+            assemble("jp missing", 0),
+            Err("Line 1: cannot evaluate `missing`".to_string())
+        );
+        assert_eq!(
+            assemble("ld a, 256", 0),
+            Err("Line 1: `256` does not fit in a byte".to_string())
+        );
+        assert_eq!(
+            assemble("ld hl, $10000", 0),
+            Err("Line 1: `$10000` does not fit in a word".to_string())
+        );
+        assert_eq!(
+            assemble("jp 70000", 0),
+            Err("Line 1: `70000` does not fit in a word".to_string())
+        );
+        assert_eq!(
+            assemble("db 1, 300", 0),
+            Err("Line 1: `300` does not fit in a byte".to_string())
+        );
+        assert_eq!(
+            assemble("dw -32769", 0),
+            Err("Line 1: `-32769` does not fit in a word".to_string())
+        );
+    }
+
+    #[test]
+    fn assembles_every_disassembled_instruction() {
+        // Every unprefixed opcode followed by operand bytes, then every prefixed one
+        let programs = (0..=0xFF)
+            .map(|opcode| match opcode {
+                // STOP is always assembled with a padding byte of 0
+                0x10 => vec![0x10, 0x00],
+                _ => vec![opcode, 0xFE, 0xC1],
+            })
+            .chain((0..=0xFF).map(|opcode| vec![0xCB, opcode]));
+
+        for program in programs {
+            let mut memory = FlatRam::new();
+            memory.load(0x0200, &program);
+
+            let disassembly = disassemble(&memory, 0x0200, 1).next().unwrap();
+
+            assert_eq!(
+                assemble(&disassembly.text, 0x0200),
+                Ok(disassembly.bytes),
+                "{}",
+                disassembly.text
+            );
+        }
+    }
+}
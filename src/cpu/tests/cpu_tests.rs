@@ -1,6 +1,5 @@
+use crate::cpu::assembler::assemble;
 use crate::cpu::*;
-#[cfg(test)]
-use crate::CPU;
 
 macro_rules! assert_flags {
     (
@@ -67,8 +66,48 @@ macro_rules! test_instruction {
     }
 }
 
-fn new_cpu() -> CPU {
-    CPU::new(None, vec![0; 0xFFFF])
+fn new_cpu() -> CPU<FlatRam> {
+    CPU::with_bus(FlatRam::new())
+}
+
+// Creates a CPU with `program` at address 0, where `pc` points to
+fn cpu_with_program(program: &[u8]) -> CPU<FlatRam> {
+    let mut memory = FlatRam::new();
+    memory.load(0x0000, program);
+
+    CPU::with_bus(memory)
+}
+
+// Creates a CPU connected to the memory map of a game with `program` at the start of the rom, for tests that depend on
+// the IO registers
+fn cpu_with_memory_map(program: &[u8]) -> CPU {
+    let mut rom = vec![0; 0x8000];
+    rom[..program.len()].copy_from_slice(program);
+
+    CPU::new(None, rom)
+}
+
+// Assembles `source`, loads it at 0x0100 like a game and runs it until it executes a `halt`
+fn run_program(source: &str) -> CPU {
+    let program = assemble(source, 0x0100).unwrap_or_else(|error| panic!("{}", error));
+    let mut rom = vec![0; 0x8000];
+    rom[0x0100..0x0100 + program.len()].copy_from_slice(&program);
+
+    let mut cpu = CPU::new(None, rom);
+    cpu.skip_boot(Model::DMG);
+    cpu.lockup_policy = LockupPolicy::ERROR;
+
+    for _ in 0..100_000 {
+        if let Err(lockup) = cpu.step() {
+            panic!("{}", lockup);
+        }
+
+        if cpu.state == CPUState::HALTED {
+            return cpu;
+        }
+    }
+
+    panic!("The program did not halt");
 }
 
 #[test]
@@ -186,7 +225,7 @@ fn execute_ccf() {
     cpu.registers.f.carry = true;
     cpu.execute_instruction(Instruction::CCF);
 
-    assert_eq!(cpu.registers.f.carry, false);
+    assert!(!cpu.registers.f.carry);
 }
 
 #[test]
@@ -200,7 +239,7 @@ fn execute_scf() {
     cpu.registers.f.carry = false;
     cpu.execute_instruction(Instruction::SCF);
 
-    assert_eq!(cpu.registers.f.carry, true);
+    assert!(cpu.registers.f.carry);
 }
 
 #[test]
@@ -209,6 +248,12 @@ fn execute_set() {
     test_instruction!(Instruction::SET(PrefixTarget::C, BitPosition::B3), c: 0b1001_0111 ; c => 0b1001_1111);
 }
 
+#[test]
+fn execute_res() {
+    test_instruction!(Instruction::RES(PrefixTarget::A, BitPosition::B7), a: 0b1111_1111 ; a => 0b0111_1111);
+    test_instruction!(Instruction::RES(PrefixTarget::E, BitPosition::B0), e: 0b0000_0000 ; e => 0b0000_0000);
+}
+
 #[test]
 fn execute_swap() {
     test_instruction!(Instruction::SWAP(PrefixTarget::A), a: 0b0000_1111 ; a => 0b1111_0000);
@@ -276,12 +321,12 @@ fn execute_rlca() {
 
 #[test]
 fn execute_rr() {
+    // RR rotates through the carry flag, bit 0 goes into the carry and the old carry goes into bit 7
     let cpu =
-        test_instruction!(Instruction::RR(PrefixTarget::A), a: 0b1001_1001 ; a => 0b1100_1100);
+        test_instruction!(Instruction::RR(PrefixTarget::A), a: 0b1001_1001 ; a => 0b0100_1100);
     assert_flags!(cpu, zero: false, subtract: false, half_carry: false, carry: true);
 
-    let cpu =
-        test_instruction!(Instruction::RR(PrefixTarget::B), b: 0b1001_1001 ; b => 0b1100_1100);
+    let cpu = test_instruction!(Instruction::RR(PrefixTarget::B), b: 0b1001_1001, f.carry: true ; b => 0b1100_1100);
     assert_flags!(cpu, zero: false, subtract: false, half_carry: false, carry: true);
 
     let cpu = test_instruction!(Instruction::RR(PrefixTarget::C), c: 0 ; c => 0);
@@ -290,9 +335,12 @@ fn execute_rr() {
 
 #[test]
 fn execute_rra() {
-    let cpu = test_instruction!(Instruction::RRA, a: 0b1001_1001 ; a => 0b1100_1100);
+    let cpu = test_instruction!(Instruction::RRA, a: 0b1001_1001 ; a => 0b0100_1100);
     assert_flags!(cpu, zero: false, subtract: false, half_carry: false, carry: true);
 
+    let cpu = test_instruction!(Instruction::RRA, a: 0b0000_0010, f.carry: true ; a => 0b1000_0001);
+    assert_flags!(cpu, zero: false, subtract: false, half_carry: false, carry: false);
+
     // RRA resets the zero flag to 0, even if the result is 0
     let cpu = test_instruction!(Instruction::RRA, a: 0 ; a => 0);
     assert_flags!(cpu, zero: false, subtract: false, half_carry: false, carry: false);
@@ -304,8 +352,13 @@ fn execute_rra() {
 
 #[test]
 fn execute_rrc() {
-    let cpu = test_instruction!(Instruction::RRC(PrefixTarget::A), a: 0b0000_0001 ; a => 0);
-    assert_flags!(cpu, zero: true, subtract: false, half_carry: false, carry: true);
+    // Bit 0 is rotated into bit 7 as well as into the carry
+    let cpu =
+        test_instruction!(Instruction::RRC(PrefixTarget::A), a: 0b0000_0001 ; a => 0b1000_0000);
+    assert_flags!(cpu, zero: false, subtract: false, half_carry: false, carry: true);
+
+    let cpu = test_instruction!(Instruction::RRC(PrefixTarget::C), c: 0 ; c => 0);
+    assert_flags!(cpu, zero: true, subtract: false, half_carry: false, carry: false);
 
     let cpu =
         test_instruction!(Instruction::RRC(PrefixTarget::B), b: 0b1000_0000 ; b => 0b0100_0000);
@@ -314,17 +367,1064 @@ fn execute_rrc() {
 
 #[test]
 fn execute_rrca() {
-    // RRA resets the zero flag to 0, even if the result is 0
-    let cpu = test_instruction!(Instruction::RRCA, a: 0b0000_0001 ; a => 0);
+    let cpu = test_instruction!(Instruction::RRCA, a: 0b0000_0001 ; a => 0b1000_0000);
     assert_flags!(cpu, zero: false, subtract: false, half_carry: false, carry: true);
 
+    // RRCA resets the zero flag to 0, even if the result is 0
+    let cpu = test_instruction!(Instruction::RRCA, a: 0 ; a => 0);
+    assert_flags!(cpu, zero: false, subtract: false, half_carry: false, carry: false);
+
     // RRCA only operates on the `a` register, the `d` register is unaffected
     let cpu = test_instruction!(Instruction::RRCA, d: 0b1001_1001 ; d => 0b1001_1001);
     assert_flags!(cpu, zero: false, subtract: false, half_carry: false, carry: false);
 }
 
+#[test]
+fn execute_sla() {
+    let cpu = test_instruction!(Instruction::SLA(PrefixTarget::A), a: 0b1100_0001, f.carry: true ; a => 0b1000_0010);
+    assert_flags!(cpu, zero: false, subtract: false, half_carry: false, carry: true);
+
+    let cpu = test_instruction!(Instruction::SLA(PrefixTarget::B), b: 0b1000_0000 ; b => 0);
+    assert_flags!(cpu, zero: true, subtract: false, half_carry: false, carry: true);
+}
+
+#[test]
+fn execute_sra() {
+    // SRA keeps bit 7 so negative numbers stay negative
+    let cpu =
+        test_instruction!(Instruction::SRA(PrefixTarget::A), a: 0b1000_0011 ; a => 0b1100_0001);
+    assert_flags!(cpu, zero: false, subtract: false, half_carry: false, carry: true);
+
+    let cpu = test_instruction!(Instruction::SRA(PrefixTarget::C), c: 0b0000_0001 ; c => 0);
+    assert_flags!(cpu, zero: true, subtract: false, half_carry: false, carry: true);
+}
+
+#[test]
+fn execute_srl() {
+    let cpu = test_instruction!(Instruction::SRL(PrefixTarget::A), a: 0b1000_0011, f.carry: true ; a => 0b0100_0001);
+    assert_flags!(cpu, zero: false, subtract: false, half_carry: false, carry: true);
+
+    let cpu =
+        test_instruction!(Instruction::SRL(PrefixTarget::D), d: 0b0000_0010 ; d => 0b0000_0001);
+    assert_flags!(cpu, zero: false, subtract: false, half_carry: false, carry: false);
+}
+
 #[test]
 fn execute_ld_8bit() {
-    test_instruction!(Instruction::LD(LoadType::BYTE(LoadByteSource::A, LoadByteTarget::B)), a: 0xFF, b);
-    test_instruction!(Instruction::LD(LoadType::BYTE(LoadByteSource::D, LoadByteTarget::L)), d: 0xCA, l);
+    test_instruction!(Instruction::LD(LoadType::BYTE(LoadByteTarget::A, LoadByteSource::B)), a: 0xFF, b);
+    test_instruction!(Instruction::LD(LoadType::BYTE(LoadByteTarget::D, LoadByteSource::L)), d: 0xCA, l);
+}
+
+#[test]
+fn step_executes_instruction_at_pc() {
+    // NOP; ADD A,B; SET 3,C
+    let mut cpu = cpu_with_program(&[0x00, 0x80, 0xCB, 0xD9]);
+    cpu.registers.a = 0x1;
+    cpu.registers.b = 0x2;
+
+    assert_eq!(cpu.step(), Ok(4));
+    assert_eq!(cpu.pc, 0x1);
+
+    assert_eq!(cpu.step(), Ok(4));
+    assert_eq!(cpu.pc, 0x2);
+    assert_eq!(cpu.registers.a, 0x3);
+
+    assert_eq!(cpu.step(), Ok(8));
+    assert_eq!(cpu.pc, 0x4);
+    assert_eq!(cpu.registers.c, 0b0000_1000);
+}
+
+#[test]
+fn step_counts_16bit_cycles() {
+    // INC HL; ADD HL,BC
+    let mut cpu = cpu_with_program(&[0x23, 0x09]);
+
+    assert_eq!(cpu.step(), Ok(8));
+    assert_eq!(cpu.step(), Ok(8));
+    assert_eq!(cpu.pc, 0x2);
+}
+
+// Creates a CPU with `hl` pointing at `value` in work RAM
+fn cpu_with_hli(value: u8) -> CPU<FlatRam> {
+    let mut cpu = new_cpu();
+    cpu.registers.set_hl(0xC000);
+    cpu.memory.write(0xC000, value);
+
+    cpu
+}
+
+#[test]
+fn execute_hli_arithmetic() {
+    let mut cpu = cpu_with_hli(0x2);
+    cpu.registers.a = 0x1;
+    assert_eq!(
+        cpu.execute_instruction(Instruction::ADD(ArithmeticTarget::HLI)),
+        8
+    );
+    assert_eq!(cpu.registers.a, 0x3);
+
+    let mut cpu = cpu_with_hli(0x3);
+    cpu.registers.a = 0x3;
+    assert_eq!(
+        cpu.execute_instruction(Instruction::CP(ArithmeticTarget::HLI)),
+        8
+    );
+    assert_eq!(cpu.registers.a, 0x3);
+    assert_flags!(cpu, zero: true, subtract: true);
+}
+
+#[test]
+fn execute_hli_inc_dec() {
+    let mut cpu = cpu_with_hli(0xFF);
+    assert_eq!(
+        cpu.execute_instruction(Instruction::INC(IncDecTarget::HLI)),
+        12
+    );
+    assert_eq!(cpu.memory.read(0xC000), 0);
+    assert_flags!(cpu, zero: true, half_carry: true);
+
+    assert_eq!(
+        cpu.execute_instruction(Instruction::DEC(IncDecTarget::HLI)),
+        12
+    );
+    assert_eq!(cpu.memory.read(0xC000), 0xFF);
+}
+
+#[test]
+fn execute_hli_prefixed() {
+    let mut cpu = cpu_with_hli(0b1000_0001);
+    assert_eq!(
+        cpu.execute_instruction(Instruction::BIT(PrefixTarget::HLI, BitPosition::B1)),
+        12
+    );
+    assert_flags!(cpu, zero: true);
+
+    assert_eq!(
+        cpu.execute_instruction(Instruction::SET(PrefixTarget::HLI, BitPosition::B1)),
+        16
+    );
+    assert_eq!(cpu.memory.read(0xC000), 0b1000_0011);
+
+    assert_eq!(
+        cpu.execute_instruction(Instruction::SWAP(PrefixTarget::HLI)),
+        16
+    );
+    assert_eq!(cpu.memory.read(0xC000), 0b0011_1000);
+}
+
+#[test]
+fn execute_hli_load() {
+    let mut cpu = cpu_with_hli(0xAB);
+    assert_eq!(
+        cpu.execute_instruction(Instruction::LD(LoadType::BYTE(
+            LoadByteTarget::B,
+            LoadByteSource::HLI
+        ))),
+        8
+    );
+    assert_eq!(cpu.registers.b, 0xAB);
+
+    cpu.registers.a = 0xCD;
+    assert_eq!(
+        cpu.execute_instruction(Instruction::LD(LoadType::BYTE(
+            LoadByteTarget::HLI,
+            LoadByteSource::A
+        ))),
+        8
+    );
+    assert_eq!(cpu.memory.read(0xC000), 0xCD);
+}
+
+#[test]
+fn execute_immediate_arithmetic() {
+    // ADD A,0x05; SUB A,0x01; CP A,0x04
+    let mut cpu = cpu_with_program(&[0xC6, 0x05, 0xD6, 0x01, 0xFE, 0x04]);
+    cpu.registers.a = 0x1;
+
+    assert_eq!(cpu.step(), Ok(8));
+    assert_eq!(cpu.registers.a, 0x6);
+    assert_eq!(cpu.pc, 0x2);
+
+    assert_eq!(cpu.step(), Ok(8));
+    assert_eq!(cpu.registers.a, 0x5);
+
+    assert_eq!(cpu.step(), Ok(8));
+    assert_eq!(cpu.registers.a, 0x5);
+    assert_flags!(cpu, zero: false, subtract: true, carry: false);
+    assert_eq!(cpu.pc, 0x6);
+}
+
+#[test]
+fn execute_immediate_load() {
+    // LD B,0x42; LD (HL),0x99
+    let mut cpu = cpu_with_program(&[0x06, 0x42, 0x36, 0x99]);
+    cpu.registers.set_hl(0xC000);
+
+    assert_eq!(cpu.step(), Ok(8));
+    assert_eq!(cpu.registers.b, 0x42);
+
+    assert_eq!(cpu.step(), Ok(12));
+    assert_eq!(cpu.memory.read(0xC000), 0x99);
+    assert_eq!(cpu.pc, 0x4);
+}
+
+#[test]
+fn execute_ld_16bit_immediate() {
+    // LD BC,0x1234; LD SP,0xFFFE
+    let mut cpu = cpu_with_program(&[0x01, 0x34, 0x12, 0x31, 0xFE, 0xFF]);
+
+    assert_eq!(cpu.step(), Ok(12));
+    assert_eq!(cpu.registers.get_bc(), 0x1234);
+
+    assert_eq!(cpu.step(), Ok(12));
+    assert_eq!(cpu.sp, 0xFFFE);
+    assert_eq!(cpu.pc, 0x6);
+}
+
+#[test]
+fn execute_ld_a16_sp() {
+    // LD (0xC010),SP
+    let mut cpu = cpu_with_program(&[0x08, 0x10, 0xC0]);
+    cpu.sp = 0xBEEF;
+
+    assert_eq!(cpu.step(), Ok(20));
+    assert_eq!(cpu.memory.read(0xC010), 0xEF);
+    assert_eq!(cpu.memory.read(0xC011), 0xBE);
+    assert_eq!(cpu.pc, 0x3);
+}
+
+#[test]
+fn execute_ld_sp_hl() {
+    let mut cpu = new_cpu();
+    cpu.registers.set_hl(0xD000);
+
+    assert_eq!(
+        cpu.execute_instruction(Instruction::LD(LoadType::WORD(
+            LoadWordTarget::SP,
+            LoadWordSource::HL
+        ))),
+        8
+    );
+    assert_eq!(cpu.sp, 0xD000);
+}
+
+#[test]
+fn execute_ld_hl_sp_e8() {
+    // LD HL,SP+0x01
+    let mut cpu = cpu_with_program(&[0xF8, 0x01]);
+    cpu.sp = 0x00FF;
+    cpu.registers.f.zero = true;
+
+    assert_eq!(cpu.step(), Ok(12));
+    assert_eq!(cpu.registers.get_hl(), 0x0100);
+    assert_flags!(cpu, zero: false, subtract: false, half_carry: true, carry: true);
+
+    // LD HL,SP-2
+    let mut cpu = cpu_with_program(&[0xF8, 0xFE]);
+    cpu.sp = 0xFFF0;
+
+    assert_eq!(cpu.step(), Ok(12));
+    assert_eq!(cpu.registers.get_hl(), 0xFFEE);
+    // 0xF0 + 0xFE overflows the low byte, but not the low nibble
+    assert_flags!(cpu, zero: false, subtract: false, half_carry: false, carry: true);
+    assert_eq!(cpu.sp, 0xFFF0);
+}
+
+#[test]
+fn execute_ld_indirect_register_pairs() {
+    // LD (BC),A; LD A,(DE)
+    let mut cpu = cpu_with_program(&[0x02, 0x1A]);
+    cpu.registers.a = 0x12;
+    cpu.registers.set_bc(0xC000);
+    cpu.registers.set_de(0xC001);
+    cpu.memory.write(0xC001, 0x34);
+
+    assert_eq!(cpu.step(), Ok(8));
+    assert_eq!(cpu.memory.read(0xC000), 0x12);
+
+    assert_eq!(cpu.step(), Ok(8));
+    assert_eq!(cpu.registers.a, 0x34);
+}
+
+#[test]
+fn execute_ld_indirect_hl_inc_dec() {
+    // LD (HL+),A; LD (HL+),A; LD A,(HL-)
+    let mut cpu = cpu_with_program(&[0x22, 0x22, 0x3A]);
+    cpu.registers.a = 0x56;
+    cpu.registers.set_hl(0xC000);
+
+    assert_eq!(cpu.step(), Ok(8));
+    assert_eq!(cpu.step(), Ok(8));
+    assert_eq!(cpu.memory.read(0xC000), 0x56);
+    assert_eq!(cpu.memory.read(0xC001), 0x56);
+    assert_eq!(cpu.registers.get_hl(), 0xC002);
+
+    cpu.memory.write(0xC002, 0x78);
+    assert_eq!(cpu.step(), Ok(8));
+    assert_eq!(cpu.registers.a, 0x78);
+    assert_eq!(cpu.registers.get_hl(), 0xC001);
+}
+
+#[test]
+fn execute_ld_high_page() {
+    // LDH (0x80),A; LD A,(C)
+    let mut cpu = cpu_with_program(&[0xE0, 0x80, 0xF2]);
+    cpu.registers.a = 0x9A;
+    cpu.registers.c = 0x81;
+    cpu.memory.write(0xFF81, 0xBC);
+
+    assert_eq!(cpu.step(), Ok(12));
+    assert_eq!(cpu.memory.read(0xFF80), 0x9A);
+    assert_eq!(cpu.pc, 0x2);
+
+    assert_eq!(cpu.step(), Ok(8));
+    assert_eq!(cpu.registers.a, 0xBC);
+    assert_eq!(cpu.pc, 0x3);
+}
+
+#[test]
+fn execute_ld_absolute() {
+    // LD (0xC123),A; LD A,(0xC124)
+    let mut cpu = cpu_with_program(&[0xEA, 0x23, 0xC1, 0xFA, 0x24, 0xC1]);
+    cpu.registers.a = 0xDE;
+    cpu.memory.write(0xC124, 0xAD);
+
+    assert_eq!(cpu.step(), Ok(16));
+    assert_eq!(cpu.memory.read(0xC123), 0xDE);
+
+    assert_eq!(cpu.step(), Ok(16));
+    assert_eq!(cpu.registers.a, 0xAD);
+    assert_eq!(cpu.pc, 0x6);
+}
+
+#[test]
+fn execute_jp() {
+    // JP NZ,0x1234; JP 0x0010
+    let mut cpu = cpu_with_program(&[0xC2, 0x34, 0x12, 0xC3, 0x10, 0x00]);
+    cpu.registers.f.zero = true;
+
+    assert_eq!(cpu.step(), Ok(12));
+    assert_eq!(cpu.pc, 0x3);
+
+    assert_eq!(cpu.step(), Ok(16));
+    assert_eq!(cpu.pc, 0x10);
+}
+
+#[test]
+fn execute_jp_hl() {
+    let mut cpu = new_cpu();
+    cpu.registers.set_hl(0x4000);
+
+    assert_eq!(cpu.execute_instruction(Instruction::JPHL), 4);
+    assert_eq!(cpu.pc, 0x4000);
+}
+
+#[test]
+fn execute_jr() {
+    // JR C,+2; JR NC,-4
+    let mut cpu = cpu_with_program(&[0x38, 0x02, 0x30, 0xFC]);
+
+    assert_eq!(cpu.step(), Ok(8));
+    assert_eq!(cpu.pc, 0x2);
+
+    assert_eq!(cpu.step(), Ok(12));
+    assert_eq!(cpu.pc, 0x0);
+
+    cpu.registers.f.carry = true;
+    assert_eq!(cpu.step(), Ok(12));
+    assert_eq!(cpu.pc, 0x4);
+}
+
+#[test]
+fn execute_call_ret() {
+    // CALL 0x0010; ...; 0x0010: RET Z; RET
+    let mut program = vec![0; 0x12];
+    program[0..3].copy_from_slice(&[0xCD, 0x10, 0x00]);
+    program[0x10..0x12].copy_from_slice(&[0xC8, 0xC9]);
+
+    let mut cpu = cpu_with_program(&program);
+    cpu.sp = 0xFFFE;
+
+    assert_eq!(cpu.step(), Ok(24));
+    assert_eq!(cpu.pc, 0x10);
+    assert_eq!(cpu.sp, 0xFFFC);
+    assert_eq!(cpu.memory.read(0xFFFC), 0x03);
+    assert_eq!(cpu.memory.read(0xFFFD), 0x00);
+
+    assert_eq!(cpu.step(), Ok(8));
+    assert_eq!(cpu.pc, 0x11);
+
+    assert_eq!(cpu.step(), Ok(16));
+    assert_eq!(cpu.pc, 0x3);
+    assert_eq!(cpu.sp, 0xFFFE);
+}
+
+#[test]
+fn execute_conditional_call_ret() {
+    // CALL C,0x0010; CALL C,0x0010; ...; 0x0010: RET C
+    let mut program = vec![0; 0x11];
+    program[0..6].copy_from_slice(&[0xDC, 0x10, 0x00, 0xDC, 0x10, 0x00]);
+    program[0x10] = 0xD8;
+
+    let mut cpu = cpu_with_program(&program);
+    cpu.sp = 0xFFFE;
+    cpu.registers.f.carry = false;
+
+    assert_eq!(cpu.step(), Ok(12));
+    assert_eq!(cpu.pc, 0x3);
+    assert_eq!(cpu.sp, 0xFFFE);
+
+    cpu.registers.f.carry = true;
+    assert_eq!(cpu.step(), Ok(24));
+    assert_eq!(cpu.pc, 0x10);
+
+    assert_eq!(cpu.step(), Ok(20));
+    assert_eq!(cpu.pc, 0x6);
+}
+
+#[test]
+fn execute_reti() {
+    let mut cpu = new_cpu();
+    cpu.sp = 0xFFFC;
+    cpu.memory.write(0xFFFC, 0x50);
+    cpu.memory.write(0xFFFD, 0x01);
+
+    assert_eq!(cpu.execute_instruction(Instruction::RETI), 16);
+    assert_eq!(cpu.pc, 0x0150);
+    assert_eq!(cpu.sp, 0xFFFE);
+    assert!(cpu.ime);
+}
+
+#[test]
+fn execute_push_pop() {
+    // PUSH BC; POP DE; PUSH AF; POP HL
+    let mut cpu = cpu_with_program(&[0xC5, 0xD1, 0xF5, 0xE1]);
+    cpu.sp = 0xFFFE;
+    cpu.registers.set_bc(0x1234);
+    cpu.registers.a = 0x56;
+    cpu.registers.f.zero = true;
+
+    assert_eq!(cpu.step(), Ok(16));
+    assert_eq!(cpu.sp, 0xFFFC);
+    assert_eq!(cpu.memory.read(0xFFFD), 0x12);
+    assert_eq!(cpu.memory.read(0xFFFC), 0x34);
+
+    assert_eq!(cpu.step(), Ok(12));
+    assert_eq!(cpu.registers.get_de(), 0x1234);
+    assert_eq!(cpu.sp, 0xFFFE);
+
+    assert_eq!(cpu.step(), Ok(16));
+    assert_eq!(cpu.step(), Ok(12));
+    assert_eq!(cpu.registers.get_hl(), 0x5680);
+}
+
+#[test]
+fn execute_pop_af() {
+    // POP AF
+    let mut cpu = cpu_with_program(&[0xF1]);
+    cpu.sp = 0xC000;
+    cpu.memory.write(0xC000, 0xFF);
+    cpu.memory.write(0xC001, 0xAB);
+
+    assert_eq!(cpu.step(), Ok(12));
+    assert_eq!(cpu.registers.a, 0xAB);
+    // The low nibble of `f` is always 0
+    assert_eq!(cpu.registers.get_af(), 0xABF0);
+    assert_flags!(cpu, zero: true, subtract: true, half_carry: true, carry: true);
+}
+
+#[test]
+fn execute_inc_dec_sp() {
+    let mut cpu = new_cpu();
+    cpu.sp = 0xFFFF;
+
+    assert_eq!(
+        cpu.execute_instruction(Instruction::INC(IncDecTarget::SP)),
+        8
+    );
+    assert_eq!(cpu.sp, 0);
+
+    assert_eq!(
+        cpu.execute_instruction(Instruction::DEC(IncDecTarget::SP)),
+        8
+    );
+    assert_eq!(cpu.sp, 0xFFFF);
+}
+
+#[test]
+fn execute_add_hl_sp() {
+    let mut cpu = new_cpu();
+    cpu.sp = 0x0FFF;
+    cpu.registers.set_hl(0x0001);
+
+    assert_eq!(
+        cpu.execute_instruction(Instruction::ADDHL(ADDHLTarget::SP)),
+        8
+    );
+    assert_eq!(cpu.registers.get_hl(), 0x1000);
+}
+
+#[test]
+fn execute_add_sp_e8() {
+    // ADD SP,-1; ADD SP,+0x10
+    let mut cpu = cpu_with_program(&[0xE8, 0xFF, 0xE8, 0x10]);
+    cpu.sp = 0x0000;
+
+    assert_eq!(cpu.step(), Ok(16));
+    assert_eq!(cpu.sp, 0xFFFF);
+    assert_flags!(cpu, zero: false, subtract: false, half_carry: false, carry: false);
+
+    assert_eq!(cpu.step(), Ok(16));
+    assert_eq!(cpu.sp, 0x000F);
+    assert_flags!(cpu, zero: false, subtract: false, half_carry: false, carry: true);
+}
+
+#[test]
+fn execute_rst() {
+    // NOP; RST 0x38
+    let mut cpu = cpu_with_program(&[0x00, 0xFF]);
+    cpu.sp = 0xFFFE;
+
+    cpu.step().unwrap();
+    assert_eq!(cpu.step(), Ok(16));
+    assert_eq!(cpu.pc, 0x38);
+    assert_eq!(cpu.sp, 0xFFFC);
+    assert_eq!(cpu.memory.read(0xFFFC), 0x02);
+    assert_eq!(cpu.memory.read(0xFFFD), 0x00);
+}
+
+#[test]
+fn dispatch_interrupt_by_priority() {
+    let mut cpu = cpu_with_program(&[0x00]);
+    cpu.sp = 0xFFFE;
+    cpu.ime = true;
+    cpu.memory.write(0xFFFF, 0b0000_0101);
+    cpu.request_interrupt(Interrupts::TIMER);
+    cpu.request_interrupt(Interrupts::VBLANK);
+
+    assert_eq!(cpu.step(), Ok(20));
+    assert_eq!(cpu.pc, 0x40);
+    assert!(!cpu.ime);
+    assert_eq!(cpu.memory.read(0xFFFC), 0x00);
+    assert_eq!(cpu.memory.read(0xFFFD), 0x00);
+    // Only the dispatched interrupt is acknowledged
+    assert_eq!(cpu.memory.read(0xFF0F), 0b0000_0100);
+}
+
+#[test]
+fn interrupts_wait_for_ime_and_ie() {
+    let mut cpu = cpu_with_program(&[0x00, 0x00]);
+    cpu.sp = 0xFFFE;
+    cpu.memory.write(0xFFFF, 0b0000_0001);
+    cpu.request_interrupt(Interrupts::SERIAL);
+    cpu.request_interrupt(Interrupts::VBLANK);
+
+    // IME is disabled
+    assert_eq!(cpu.step(), Ok(4));
+    assert_eq!(cpu.pc, 0x1);
+
+    // SERIAL is not enabled in IE
+    cpu.ime = true;
+    cpu.memory.write(0xFFFF, 0b0000_1000);
+    cpu.memory.write(0xFF0F, 0b0000_0001);
+    assert_eq!(cpu.step(), Ok(4));
+    assert_eq!(cpu.pc, 0x2);
+}
+
+#[test]
+fn upper_bits_of_ie_enable_no_interrupt() {
+    // The unused upper bits of IF always read as 1 on the memory map
+    let mut cpu = cpu_with_memory_map(&[0x00, 0x00]);
+    cpu.sp = 0xFFFE;
+    cpu.ime = true;
+    cpu.memory.write(0xFFFF, 0b1110_0000);
+
+    assert_eq!(cpu.memory.read(0xFFFF), 0b1110_0000);
+    assert_eq!(cpu.step(), Ok(4));
+    assert_eq!(cpu.pc, 0x1);
+}
+
+#[test]
+fn execute_ei_is_delayed() {
+    // EI; NOP; NOP
+    let mut cpu = cpu_with_program(&[0xFB, 0x00, 0x00]);
+    cpu.sp = 0xFFFE;
+    cpu.memory.write(0xFFFF, 0b0001_0000);
+    cpu.request_interrupt(Interrupts::JOYPAD);
+
+    assert_eq!(cpu.step(), Ok(4));
+    assert!(!cpu.ime);
+
+    // The instruction after EI still runs before the interrupt is dispatched
+    assert_eq!(cpu.step(), Ok(4));
+    assert_eq!(cpu.pc, 0x2);
+    assert!(cpu.ime);
+
+    assert_eq!(cpu.step(), Ok(20));
+    assert_eq!(cpu.pc, 0x60);
+    assert_eq!(cpu.memory.read(0xFFFC), 0x02);
+}
+
+#[test]
+fn execute_di_cancels_ei() {
+    // EI; DI; NOP
+    let mut cpu = cpu_with_program(&[0xFB, 0xF3, 0x00]);
+    cpu.memory.write(0xFFFF, 0b0000_0001);
+    cpu.request_interrupt(Interrupts::VBLANK);
+
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+    assert_eq!(cpu.step(), Ok(4));
+    assert_eq!(cpu.pc, 0x3);
+    assert!(!cpu.ime);
+}
+
+#[test]
+fn execute_halt_until_interrupt() {
+    // HALT; NOP
+    let mut cpu = cpu_with_program(&[0x76, 0x00]);
+    cpu.sp = 0xFFFE;
+    cpu.ime = true;
+    cpu.memory.write(0xFFFF, 0b0000_0100);
+
+    assert_eq!(cpu.step(), Ok(4));
+    assert_eq!(cpu.state, CPUState::HALTED);
+
+    assert_eq!(cpu.step(), Ok(4));
+    assert_eq!(cpu.pc, 0x1);
+
+    cpu.request_interrupt(Interrupts::TIMER);
+    assert_eq!(cpu.step(), Ok(20));
+    assert_eq!(cpu.state, CPUState::RUNNING);
+    assert_eq!(cpu.pc, 0x50);
+}
+
+#[test]
+fn execute_halt_without_ime() {
+    // HALT; INC A
+    let mut cpu = cpu_with_program(&[0x76, 0x3C]);
+    cpu.memory.write(0xFFFF, 0b0000_0001);
+
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+    assert_eq!(cpu.state, CPUState::HALTED);
+
+    // The interrupt wakes the CPU up, but isn't dispatched
+    cpu.request_interrupt(Interrupts::VBLANK);
+    assert_eq!(cpu.step(), Ok(4));
+    assert_eq!(cpu.state, CPUState::RUNNING);
+    assert_eq!(cpu.registers.a, 0x1);
+    assert_eq!(cpu.pc, 0x2);
+}
+
+#[test]
+fn execute_halt_bug() {
+    // HALT; INC A; NOP
+    let mut cpu = cpu_with_program(&[0x76, 0x3C, 0x00]);
+    cpu.memory.write(0xFFFF, 0b0000_0001);
+    cpu.request_interrupt(Interrupts::VBLANK);
+
+    cpu.step().unwrap();
+    assert_eq!(cpu.state, CPUState::RUNNING);
+
+    // INC A is read twice
+    cpu.step().unwrap();
+    assert_eq!(cpu.pc, 0x1);
+    cpu.step().unwrap();
+    assert_eq!(cpu.pc, 0x2);
+    assert_eq!(cpu.registers.a, 0x2);
+}
+
+#[test]
+fn execute_stop() {
+    // STOP; INC A
+    let mut cpu = cpu_with_memory_map(&[0x10, 0x00, 0x3C]);
+    cpu.memory.divider = 0xAB00;
+
+    assert_eq!(cpu.step(), Ok(4));
+    assert_eq!(cpu.state, CPUState::STOPPED);
+    assert_eq!(cpu.pc, 0x2);
+    assert_eq!(cpu.memory.read(0xFF04), 0);
+
+    cpu.step().unwrap();
+    assert_eq!(cpu.registers.a, 0);
+
+    cpu.request_interrupt(Interrupts::JOYPAD);
+    cpu.step().unwrap();
+    assert_eq!(cpu.state, CPUState::RUNNING);
+    assert_eq!(cpu.registers.a, 0x1);
+}
+
+#[test]
+fn execute_stop_speed_switch() {
+    // STOP; INC A
+    let mut cpu = cpu_with_memory_map(&[0x10, 0x00, 0x3C]);
+    cpu.memory.cgb = true;
+    cpu.memory.write(0xFF4D, 0x01);
+
+    cpu.step().unwrap();
+    assert_eq!(cpu.state, CPUState::RUNNING);
+    assert!(cpu.memory.double_speed);
+    assert_eq!(cpu.memory.read(0xFF4D), 0xFE);
+
+    cpu.step().unwrap();
+    assert_eq!(cpu.registers.a, 0x1);
+}
+
+// Reference DAA that adjusts one digit at a time, returning the new `a` and carry flag
+fn reference_daa(a: u8, subtract: bool, half_carry: bool, carry: bool) -> (u8, bool) {
+    let mut result = a as u16;
+    let mut carry_out = carry;
+
+    if subtract {
+        if half_carry {
+            result = result.wrapping_sub(0x06) & 0xFF;
+        }
+
+        if carry {
+            result = result.wrapping_sub(0x60);
+        }
+    } else {
+        if half_carry || (result & 0xF) > 0x9 {
+            result += 0x06;
+        }
+
+        if carry || result > 0x9F {
+            result += 0x60;
+            carry_out = true;
+        }
+    }
+
+    ((result & 0xFF) as u8, carry_out)
+}
+
+#[test]
+fn execute_daa_every_input() {
+    for a in 0..=0xFF {
+        for flags in 0..0b1000 {
+            let subtract = flags & 0b100 != 0;
+            let half_carry = flags & 0b010 != 0;
+            let carry = flags & 0b001 != 0;
+
+            let mut cpu = new_cpu();
+            cpu.registers.a = a;
+            cpu.registers.f.subtract = subtract;
+            cpu.registers.f.half_carry = half_carry;
+            cpu.registers.f.carry = carry;
+            assert_eq!(cpu.execute_instruction(Instruction::DAA), 4);
+
+            let (expected, expected_carry) = reference_daa(a, subtract, half_carry, carry);
+            let input = format!(
+                "a: 0x{:02X}, n: {}, h: {}, c: {}",
+                a, subtract, half_carry, carry
+            );
+            assert_eq!(cpu.registers.a, expected, "{}", input);
+            assert_eq!(cpu.registers.f.zero, expected == 0, "{}", input);
+            assert_eq!(cpu.registers.f.subtract, subtract, "{}", input);
+            assert!(!cpu.registers.f.half_carry, "{}", input);
+            assert_eq!(cpu.registers.f.carry, expected_carry, "{}", input);
+        }
+    }
+}
+
+#[test]
+fn execute_daa_after_bcd_arithmetic() {
+    let to_bcd = |value: u8| ((value / 10) << 4) | (value % 10);
+
+    for x in 0..100 {
+        for y in 0..100 {
+            // ADD A,B; DAA
+            let mut cpu = cpu_with_program(&[0x80, 0x27]);
+            cpu.registers.a = to_bcd(x);
+            cpu.registers.b = to_bcd(y);
+            cpu.step().unwrap();
+            cpu.step().unwrap();
+
+            assert_eq!(cpu.registers.a, to_bcd((x + y) % 100), "{} + {}", x, y);
+            assert_eq!(cpu.registers.f.carry, x + y >= 100, "{} + {}", x, y);
+
+            // SUB A,B; DAA
+            let mut cpu = cpu_with_program(&[0x90, 0x27]);
+            cpu.registers.a = to_bcd(x);
+            cpu.registers.b = to_bcd(y);
+            cpu.step().unwrap();
+            cpu.step().unwrap();
+
+            let difference = (x as i16 - y as i16).rem_euclid(100) as u8;
+            assert_eq!(cpu.registers.a, to_bcd(difference), "{} - {}", x, y);
+            assert_eq!(cpu.registers.f.carry, x < y, "{} - {}", x, y);
+        }
+    }
+}
+
+#[test]
+fn execute_illegal_opcode_freezes() {
+    // NOP; JP 0x4000; ...; 0x4000: 0xD3
+    let mut program = vec![0; 0x4001];
+    program[1..4].copy_from_slice(&[0xC3, 0x00, 0x40]);
+    program[0x4000] = 0xD3;
+
+    let mut cpu = cpu_with_memory_map(&program);
+    cpu.memory.write(0xFFFF, 0b0000_0001);
+    cpu.ime = true;
+
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+    assert_eq!(cpu.step(), Ok(4));
+
+    let lockup = CPULockup {
+        opcode: 0xD3,
+        pc: 0x4000,
+        bank: 1,
+    };
+    assert_eq!(cpu.state, CPUState::LOCKED(lockup));
+    assert_eq!(
+        lockup.to_string(),
+        "CPU locked up on illegal opcode 0xD3 at 01:4000"
+    );
+
+    // Not even interrupts get the CPU going again
+    cpu.request_interrupt(Interrupts::VBLANK);
+    assert_eq!(cpu.step(), Ok(4));
+    assert_eq!(cpu.pc, 0x4001);
+}
+
+#[test]
+fn execute_illegal_opcode_error_policy() {
+    let mut cpu = cpu_with_program(&[0x00, 0xFD]);
+    cpu.lockup_policy = LockupPolicy::ERROR;
+
+    assert_eq!(cpu.step(), Ok(4));
+
+    let lockup = CPULockup {
+        opcode: 0xFD,
+        pc: 0x1,
+        bank: 0,
+    };
+    assert_eq!(cpu.step(), Err(lockup));
+    assert_eq!(cpu.step(), Err(lockup));
+}
+
+#[test]
+fn execute_illegal_opcode_debugger_policy() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    static HOOK_CALLED: AtomicBool = AtomicBool::new(false);
+
+    fn hook(cpu: &CPU<FlatRam>, lockup: &CPULockup) {
+        assert_eq!(cpu.pc, 0x1);
+        assert_eq!(lockup.opcode, 0xE4);
+        assert_eq!(lockup.pc, 0x0);
+        HOOK_CALLED.store(true, Ordering::SeqCst);
+    }
+
+    let mut cpu = cpu_with_program(&[0xE4]);
+    cpu.lockup_policy = LockupPolicy::DEBUGGER(hook);
+
+    assert_eq!(cpu.step(), Ok(4));
+    assert!(matches!(cpu.state, CPUState::LOCKED(_)));
+    assert!(HOOK_CALLED.load(Ordering::SeqCst));
+}
+
+#[test]
+fn memory_access_happens_mid_instruction() {
+    // LDH A, (0x04) reads DIV during its third M-cycle, after the fetch of the opcode and of the operand
+    let mut cpu = cpu_with_memory_map(&[0xF0, 0x04, 0xF0, 0x04]);
+    cpu.memory.divider = 0xF3;
+
+    assert_eq!(cpu.step(), Ok(12));
+    assert_eq!(cpu.registers.a, 0x0);
+
+    // This time the divider ticks over to 0x100 right before the read
+    cpu.memory.divider = 0xF4;
+
+    assert_eq!(cpu.step(), Ok(12));
+    assert_eq!(cpu.registers.a, 0x1);
+}
+
+#[test]
+fn memory_write_happens_on_last_cycle() {
+    // LDH (0x04), A resets DIV on its last M-cycle, so no time passes after the reset
+    let mut cpu = cpu_with_memory_map(&[0xE0, 0x04]);
+    cpu.memory.divider = 0x1234;
+
+    assert_eq!(cpu.step(), Ok(12));
+    assert_eq!(cpu.memory.divider, 0);
+}
+
+#[test]
+fn step_advances_system_by_its_cycles() {
+    // NOP, CALL 0x0010, LD (HL+), A, JR -2 at 0x0010
+    let mut cpu = cpu_with_memory_map(&[0x00, 0xCD, 0x10, 0x00]);
+    cpu.memory.bus[0x10] = 0x22;
+    cpu.memory.bus[0x11] = 0x18;
+    cpu.memory.bus[0x12] = 0xFE;
+    cpu.sp = 0xFFFE;
+    cpu.registers.set_hl(0xC000);
+
+    let mut total = 0;
+
+    for _ in 0..4 {
+        total += cpu.step().unwrap() as u16;
+        assert_eq!(cpu.memory.divider, total);
+    }
+
+    assert_eq!(total, 4 + 24 + 8 + 12);
+}
+
+#[test]
+fn skip_boot_registers() {
+    let mut cpu = cpu_with_memory_map(&[]);
+    cpu.memory.bus[0x014D] = 0x42;
+    cpu.skip_boot(Model::DMG);
+
+    assert_eq!(cpu.registers.get_af(), 0x01B0);
+    assert_eq!(cpu.registers.get_bc(), 0x0013);
+    assert_eq!(cpu.registers.get_de(), 0x00D8);
+    assert_eq!(cpu.registers.get_hl(), 0x014D);
+    assert_eq!(cpu.sp, 0xFFFE);
+    assert_eq!(cpu.pc, 0x0100);
+    assert_eq!(cpu.memory.read(0xFF44), 0x00);
+
+    cpu.skip_boot(Model::CGB);
+
+    assert_eq!(cpu.registers.get_af(), 0x1180);
+    assert_eq!(cpu.registers.get_de(), 0xFF56);
+    assert_eq!(cpu.registers.get_hl(), 0x000D);
+    assert!(cpu.memory.cgb);
+}
+
+#[test]
+fn skip_boot_header_checksum_flags() {
+    let mut cpu = cpu_with_memory_map(&[]);
+    cpu.skip_boot(Model::MGB);
+
+    // A zero header checksum leaves only the zero flag set
+    assert_eq!(cpu.registers.get_af(), 0xFF80);
+    assert_flags!(cpu, zero: true, subtract: false, half_carry: false, carry: false);
+}
+
+#[test]
+fn dispatch_table_matches_decoded_instructions() {
+    // Every unprefixed opcode, then every prefixed one behind 0xCB
+    let programs = (0..=0xFF)
+        .map(|opcode| vec![opcode, 0x34, 0xC1])
+        .chain((0..=0xFF).map(|opcode| vec![0xCB, opcode, 0xC1]));
+
+    for program in programs {
+        let mut table = cpu_with_program(&program);
+        let mut decoded = cpu_with_program(&program);
+
+        for cpu in [&mut table, &mut decoded] {
+            cpu.sp = 0xDFF0;
+            cpu.registers.set_af(0x12B0);
+            cpu.registers.set_bc(0x3456);
+            cpu.registers.set_de(0x789A);
+            cpu.registers.set_hl(0xC123);
+        }
+
+        assert_eq!(table.step(), decoded.step_decoded(), "{:02X?}", program);
+        assert_eq!(table.pc, decoded.pc, "{:02X?}", program);
+        assert_eq!(table.sp, decoded.sp, "{:02X?}", program);
+        assert_eq!(table.registers.get_af(), decoded.registers.get_af());
+        assert_eq!(table.registers.get_bc(), decoded.registers.get_bc());
+        assert_eq!(table.registers.get_de(), decoded.registers.get_de());
+        assert_eq!(table.registers.get_hl(), decoded.registers.get_hl());
+        assert_eq!(table.state, decoded.state, "{:02X?}", program);
+        assert!(
+            table.memory.bytes == decoded.memory.bytes,
+            "{:02X?}",
+            program
+        );
+    }
+}
+
+#[test]
+fn program_sums_a_table() {
+    let cpu = run_program(
+        "
+            ld hl, table
+            ld b, 4
+            xor a
+        loop:
+            add [hl]
+            inc hl
+            dec b
+            jr nz, loop
+            halt
+
+        table:
+            db 1, 2, 3, $10
+        ",
+    );
+
+    assert_eq!(cpu.registers.a, 0x16);
+    assert_eq!(cpu.registers.b, 0);
+}
+
+#[test]
+fn program_calls_subroutine() {
+    let cpu = run_program(
+        "
+            ld sp, $DFFF
+            ld a, [value]
+            call double
+            ld [$C000], a
+            halt
+
+        double:
+            sla a
+            ret
+
+        value:
+            dw $0021
+        ",
+    );
+
+    assert_eq!(cpu.memory.read(0xC000), 0x42);
+    assert_eq!(cpu.sp, 0xDFFF);
+}
+
+#[test]
+fn program_copies_memory() {
+    let cpu = run_program(
+        "
+            ld de, source
+            ld hl, $C100
+            ld c, 3
+        copy:
+            ld a, [de]
+            ld [hl+], a
+            inc de
+            dec c
+            jr nz, copy
+            halt
+
+        source:
+            db $DE, $AD, $BE
+        ",
+    );
+
+    assert_eq!(
+        cpu.memory.read_byte_range(0xC100..0xC103),
+        [0xDE, 0xAD, 0xBE]
+    );
+    assert_eq!(cpu.registers.get_hl(), 0xC103);
+}
+
+#[test]
+fn will_execute_instruction() {
+    let mut cpu = new_cpu();
+    assert!(cpu.will_execute_instruction());
+
+    cpu.state = CPUState::HALTED;
+    assert!(!cpu.will_execute_instruction());
+
+    // A pending interrupt wakes the CPU up, it is only dispatched instead of the next instruction when `ime` is set
+    cpu.memory.write(0xFFFF, 0b0000_0001);
+    cpu.request_interrupt(Interrupts::VBLANK);
+    assert!(cpu.will_execute_instruction());
+
+    cpu.ime = true;
+    assert!(!cpu.will_execute_instruction());
 }
@@ -0,0 +1,320 @@
+//! Runs the SingleStepTests sm83 conformance tests (https://github.com/SingleStepTests/sm83).
+//!
+//! Every test describes the registers and RAM before and after executing a single instruction, and the address, value
+//! and kind of bus access of every M-cycle in between. The full suite is run when `SM83_TESTS_DIR` points at the `v1`
+//! directory of a checkout.
+//!
+//! A handful of regression tests in the same format live in `tests/sm83_regressions`, for the rotates and DAA that got
+//! their flags wrong before. They are written by hand from the documented hardware behaviour and named after the
+//! instruction and its inputs rather than the suite's convention, so they can't be mistaken for suite data. Only the
+//! suite checks the CPU against hardware.
+
+use crate::cpu::*;
+use serde_json::Value;
+use std::cell::{Cell, RefCell};
+use std::path::Path;
+
+const FIXTURES: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/src/cpu/tests/sm83_regressions"
+);
+
+/// What the CPU did on the bus during an M-cycle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BusActivity {
+    // An internal cycle without a memory access
+    IDLE,
+    READ(u16, u8),
+    WRITE(u16, u8),
+}
+
+/// Flat RAM that records the bus activity of every M-cycle.
+///
+/// The CPU ticks the bus before every memory access, so every tick starts an idle M-cycle which the first read or
+/// write after it replaces. Any other reads, such as the IF and IE checks before an instruction is fetched, don't take
+/// an M-cycle and aren't recorded.
+struct TracingBus {
+    ram: FlatRam,
+    cycles: RefCell<Vec<BusActivity>>,
+    // Whether the last M-cycle has accessed memory already
+    accessed: Cell<bool>,
+}
+
+impl TracingBus {
+    fn new() -> Self {
+        TracingBus {
+            ram: FlatRam::new(),
+            cycles: RefCell::new(Vec::new()),
+            accessed: Cell::new(true),
+        }
+    }
+
+    fn record(&self, activity: BusActivity) {
+        if !self.accessed.replace(true) {
+            if let Some(last) = self.cycles.borrow_mut().last_mut() {
+                *last = activity;
+            }
+        }
+    }
+}
+
+impl Bus for TracingBus {
+    fn read(&self, address: u16) -> u8 {
+        let value = self.ram.read(address);
+        self.record(BusActivity::READ(address, value));
+
+        value
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.ram.write(address, value);
+        self.record(BusActivity::WRITE(address, value));
+    }
+
+    fn tick(&mut self) {
+        self.cycles.get_mut().push(BusActivity::IDLE);
+        self.accessed.set(false);
+    }
+}
+
+#[derive(Default)]
+struct Summary {
+    passed: usize,
+    failures: Vec<String>,
+}
+
+impl Summary {
+    fn assert_passed(&self) {
+        assert!(
+            self.failures.is_empty(),
+            "{} of {} tests failed:\n{}",
+            self.failures.len(),
+            self.passed + self.failures.len(),
+            self.failures.join("\n")
+        );
+    }
+}
+
+#[test]
+fn single_step_regressions() {
+    let summary = run_directory(Path::new(FIXTURES));
+
+    summary.assert_passed();
+    assert!(summary.passed > 0);
+}
+
+#[test]
+fn single_step_tests() {
+    let directory = match std::env::var("SM83_TESTS_DIR") {
+        Ok(directory) if Path::new(&directory).is_dir() => directory,
+        _ => {
+            eprintln!(
+                "SM83_TESTS_DIR is not set to a directory, skipping the SingleStepTests suite"
+            );
+            return;
+        }
+    };
+
+    let summary = run_directory(Path::new(&directory));
+    eprintln!(
+        "SingleStepTests: {} passed, {} failed",
+        summary.passed,
+        summary.failures.len()
+    );
+
+    summary.assert_passed();
+}
+
+fn run_directory(directory: &Path) -> Summary {
+    let mut files: Vec<_> = std::fs::read_dir(directory)
+        .expect("The test directory can be read")
+        .map(|entry| entry.expect("The test directory can be read").path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .collect();
+    files.sort();
+
+    let mut summary = Summary::default();
+
+    for file in files {
+        let text = std::fs::read_to_string(&file).expect("The test file can be read");
+        let tests = match serde_json::from_str(&text) {
+            Ok(Value::Array(tests)) => tests,
+            Ok(_) => panic!("{}: expected an array of tests", file.display()),
+            Err(error) => panic!("{}: {}", file.display(), error),
+        };
+
+        for test in &tests {
+            let name = test
+                .get("name")
+                .and_then(Value::as_str)
+                .unwrap_or("unnamed");
+
+            match run_test(test) {
+                Ok(differences) if differences.is_empty() => summary.passed += 1,
+                Ok(differences) => {
+                    summary
+                        .failures
+                        .push(format!("{}: {}", name, differences.join(", ")))
+                }
+                Err(error) => panic!("{}: {}: {}", file.display(), name, error),
+            }
+        }
+    }
+
+    summary
+}
+
+// Returns the differences between the state after executing the instruction and the expected state
+fn run_test(test: &Value) -> Result<Vec<String>, String> {
+    let initial = field(test, "initial")?;
+    let expected = field(test, "final")?;
+    let cycles = field(test, "cycles")?
+        .as_array()
+        .ok_or("`cycles` is not an array")?;
+
+    let initial_ram = ram(initial)?;
+    let final_ram = ram(expected)?;
+    let expected_cycles = cycles
+        .iter()
+        .map(bus_activity)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut cpu = CPU::with_bus(TracingBus::new());
+    cpu.pc = number(initial, "pc")? as u16;
+    cpu.sp = number(initial, "sp")? as u16;
+    cpu.registers
+        .set_af((number(initial, "a")? << 8 | number(initial, "f")?) as u16);
+    cpu.registers
+        .set_bc((number(initial, "b")? << 8 | number(initial, "c")?) as u16);
+    cpu.registers
+        .set_de((number(initial, "d")? << 8 | number(initial, "e")?) as u16);
+    cpu.registers
+        .set_hl((number(initial, "h")? << 8 | number(initial, "l")?) as u16);
+    cpu.ime = number(initial, "ime")? != 0;
+    cpu.ime_scheduled = initial
+        .get("ei")
+        .and_then(Value::as_u64)
+        .is_some_and(|ei| ei != 0);
+
+    for (address, value) in initial_ram {
+        cpu.memory.ram.write(address, value);
+    }
+    cpu.memory
+        .ram
+        .write(INTERRUPT_ENABLE as u16, number(initial, "ie")? as u8);
+
+    cpu.step().map_err(|lockup| lockup.to_string())?;
+
+    let mut differences = Vec::new();
+    let mut compare = |name: &str, actual: u64| -> Result<(), String> {
+        let expected = number(expected, name)?;
+
+        if actual != expected {
+            differences.push(format!(
+                "{} is 0x{:X}, expected 0x{:X}",
+                name, actual, expected
+            ));
+        }
+
+        Ok(())
+    };
+
+    compare("pc", cpu.pc as u64)?;
+    compare("sp", cpu.sp as u64)?;
+    compare("a", cpu.registers.a as u64)?;
+    compare("f", u8::from(cpu.registers.f) as u64)?;
+    compare("b", cpu.registers.b as u64)?;
+    compare("c", cpu.registers.c as u64)?;
+    compare("d", cpu.registers.d as u64)?;
+    compare("e", cpu.registers.e as u64)?;
+    compare("h", cpu.registers.h as u64)?;
+    compare("l", cpu.registers.l as u64)?;
+    compare("ime", cpu.ime as u64)?;
+    compare("ie", cpu.memory.ram.read(INTERRUPT_ENABLE as u16) as u64)?;
+
+    for (address, value) in final_ram {
+        let actual = cpu.memory.ram.read(address);
+
+        if actual != value {
+            differences.push(format!(
+                "[0x{:04X}] is 0x{:02X}, expected 0x{:02X}",
+                address, actual, value
+            ));
+        }
+    }
+
+    let actual_cycles = cpu.memory.cycles.take();
+
+    if !cycles_match(&actual_cycles, &expected_cycles) {
+        differences.push(format!(
+            "bus activity is {:?}, expected {:?}",
+            actual_cycles, expected_cycles
+        ));
+    }
+
+    Ok(differences)
+}
+
+// The tests record the address and value that happen to be on the bus during idle cycles, only the kind is compared
+fn cycles_match(actual: &[BusActivity], expected: &[BusActivity]) -> bool {
+    actual.len() == expected.len()
+        && actual
+            .iter()
+            .zip(expected)
+            .all(|(actual, expected)| match expected {
+                BusActivity::IDLE => *actual == BusActivity::IDLE,
+                _ => actual == expected,
+            })
+}
+
+fn field<'a>(json: &'a Value, name: &str) -> Result<&'a Value, String> {
+    json.get(name).ok_or_else(|| format!("missing `{}`", name))
+}
+
+fn number(json: &Value, name: &str) -> Result<u64, String> {
+    field(json, name)?
+        .as_u64()
+        .ok_or_else(|| format!("`{}` is not a number", name))
+}
+
+fn ram(state: &Value) -> Result<Vec<(u16, u8)>, String> {
+    field(state, "ram")?
+        .as_array()
+        .ok_or("`ram` is not an array")?
+        .iter()
+        .map(|entry| match entry.as_array().map(Vec::as_slice) {
+            Some([address, value]) => address
+                .as_u64()
+                .zip(value.as_u64())
+                .map(|(address, value)| (address as u16, value as u8))
+                .ok_or_else(|| format!("invalid RAM entry {:?}", entry)),
+            _ => Err(format!("invalid RAM entry {:?}", entry)),
+        })
+        .collect()
+}
+
+// A cycle is `[address, value, kind]` or `null` when nothing happened on the bus, `kind` is `r-m` for a read, `-wm` for
+// a write and `---` for an idle cycle
+fn bus_activity(cycle: &Value) -> Result<BusActivity, String> {
+    let [address, value, kind] = match cycle {
+        Value::Null => return Ok(BusActivity::IDLE),
+        Value::Array(cycle) => match cycle.as_slice() {
+            [address, value, kind] => [address, value, kind],
+            _ => return Err(format!("invalid cycle {:?}", cycle)),
+        },
+        _ => return Err(format!("invalid cycle {:?}", cycle)),
+    };
+
+    let address = address.as_u64().unwrap_or(0) as u16;
+    let value = value.as_u64().unwrap_or(0) as u8;
+
+    match kind.as_str() {
+        Some(kind) if kind.starts_with('r') => Ok(BusActivity::READ(address, value)),
+        Some(kind) if kind.get(1..2) == Some("w") => Ok(BusActivity::WRITE(address, value)),
+        Some(_) => Ok(BusActivity::IDLE),
+        None => Err(format!("invalid cycle kind {:?}", kind)),
+    }
+}
@@ -0,0 +1,80 @@
+use crate::bus::Bus;
+use crate::cpu::CPU;
+use crate::test_rom::{self, Outcome};
+use std::io::Write;
+
+/// Formats the registers and the 4 bytes at `pc` the way Gameboy Doctor (https://github.com/robert/gameboy-doctor)
+/// expects them, e.g. `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`.
+pub fn trace_line<B: Bus>(cpu: &CPU<B>) -> String {
+    let registers = &cpu.registers;
+    let pcmem: Vec<String> = (0..4)
+        .map(|offset| format!("{:02X}", cpu.memory.read(cpu.pc.wrapping_add(offset))))
+        .collect();
+
+    format!(
+        "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{}",
+        registers.a,
+        u8::from(registers.f),
+        registers.b,
+        registers.c,
+        registers.d,
+        registers.e,
+        registers.h,
+        registers.l,
+        cpu.sp,
+        cpu.pc,
+        pcmem.join(",")
+    )
+}
+
+/// Runs a blargg test rom like `test_rom::run`, writing a trace line to `log` before every instruction that is
+/// executed. LY reads 0x90 like in the Gameboy Doctor logs.
+pub fn run(cpu: &mut CPU, mut log: impl Write, timeout_cycles: u64) -> std::io::Result<Outcome> {
+    let outcome = test_rom::run_with(cpu, timeout_cycles, |cpu| -> std::io::Result<()> {
+        if cpu.will_execute_instruction() {
+            writeln!(log, "{}", trace_line(cpu))?;
+        }
+
+        Ok(())
+    })?;
+    log.flush()?;
+
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use crate::model::Model;
+    use crate::test_rom::test_support::cpu_with_program;
+
+    #[test]
+    fn formats_the_state_before_an_instruction() {
+        // The header checksum is not 0, so the half carry and carry flags are set
+        let mut cpu = cpu_with_program(&[0x00, 0xC3, 0x13, 0x02]);
+        cpu.memory.bus[0x014D] = 0x01;
+        cpu.skip_boot(Model::DMG);
+
+        assert_eq!(
+            trace_line(&cpu),
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02"
+        );
+    }
+
+    #[test]
+    fn traces_every_executed_instruction() {
+        // LDH A, (0x44); HALT with an interrupt pending in IF only, so the CPU stays halted
+        let mut cpu = cpu_with_program(&[0xF0, 0x44, 0x76]);
+        let mut log = Vec::new();
+
+        assert_eq!(run(&mut cpu, &mut log, 100).unwrap(), Outcome::TIMEOUT);
+        assert_eq!(cpu.registers.a, 0x90);
+
+        let log = String::from_utf8(log).unwrap();
+        let pcs: Vec<&str> = log
+            .lines()
+            .map(|line| &line[line.find("PC:").unwrap()..][..7])
+            .collect();
+        assert_eq!(pcs, ["PC:0100", "PC:0102"]);
+    }
+}
@@ -1,9 +1,16 @@
-#![feature(slice_pattern)]
-
+mod bench;
+mod bus;
 mod cpu;
+mod doctor;
+// The memory map constants document every region, not all of them are routed anywhere yet
+#[allow(dead_code)]
 mod memory;
+mod model;
+mod mooneye;
+mod test_rom;
 
-use crate::cpu::CPU;
+use crate::cpu::{disassembler, CPULockup, LockupPolicy, CPU};
+use crate::model::Model;
 
 fn main() {
     let args = std::env::args();
@@ -11,6 +18,99 @@ fn main() {
 
     let rom = arg_iter.next();
 
+    if rom.as_deref() == Some("--bench") {
+        let million_cycles = arg_iter
+            .next()
+            .map(|cycles| cycles.parse().expect("Cannot parse the number of cycles"))
+            .unwrap_or(100);
+
+        bench::run(million_cycles);
+        return;
+    }
+
+    // Runs a blargg test rom headless, the exit code is 0 when it passes, 1 when it fails and 2 when it times out.
+    // Roms without a memory bank controller or with MBC1, such as the combined cpu_instrs.gb, are supported.
+    if rom.as_deref() == Some("--test-rom") {
+        let rom_file = arg_iter
+            .next()
+            .expect("Cannot run a test rom without a rom");
+        let seconds = timeout_seconds(arg_iter.next(), 60);
+
+        std::process::exit(run_test_rom(&rom_file, seconds));
+    }
+
+    // Runs a blargg test rom like `--test-rom`, writing a Gameboy Doctor trace line to a file before every instruction
+    if rom.as_deref() == Some("--doctor") {
+        let rom_file = arg_iter
+            .next()
+            .expect("Cannot run a test rom without a rom");
+        let log_file = arg_iter
+            .next()
+            .expect("Cannot write a trace without a log file");
+        let seconds = timeout_seconds(arg_iter.next(), 60);
+
+        let log = std::fs::File::create(&log_file)
+            .unwrap_or_else(|_| panic!("Cannot create file at path: {}", log_file));
+
+        let mut cpu = test_rom::load(std::path::Path::new(&rom_file)).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            std::process::exit(test_rom::Outcome::FAILED.exit_code());
+        });
+
+        let outcome = doctor::run(
+            &mut cpu,
+            std::io::BufWriter::new(log),
+            seconds * test_rom::CYCLES_PER_SECOND,
+        )
+        .unwrap_or_else(|error| panic!("Cannot write the trace to {}: {}", log_file, error));
+        println!("{}", String::from_utf8_lossy(&cpu.memory.serial_output));
+
+        std::process::exit(outcome.exit_code());
+    }
+
+    // Runs every Mooneye test rom in a directory, the exit code is 1 unless all of them ran and passed
+    if rom.as_deref() == Some("--mooneye") {
+        let directory = arg_iter
+            .next()
+            .expect("Cannot run the Mooneye tests without a directory");
+        let seconds = timeout_seconds(arg_iter.next(), 10);
+
+        let directory = std::path::Path::new(&directory);
+        let reports = mooneye::run_directory(directory, seconds * test_rom::CYCLES_PER_SECOND)
+            .unwrap_or_else(|error| {
+                panic!("Cannot read the roms in {}: {}", directory.display(), error)
+            });
+        mooneye::print_summary(directory, &reports);
+
+        std::process::exit(if mooneye::all_passed(&reports) { 0 } else { 1 });
+    }
+
+    // Options come before the rom: `--lockup freeze|error|debugger` picks what happens when the CPU locks up on an
+    // illegal opcode. `error`, the default, prints the lockup and exits, `debugger` also prints the registers and the
+    // disassembly from the illegal opcode on and `freeze` keeps running without executing instructions like the
+    // hardware does.
+    // `--model dmg0|dmg|mgb|sgb|sgb2|cgb|agb` picks the model whose boot rom state the rom starts in, `dmg` by default.
+    let mut rom = rom;
+    let mut lockup_policy = LockupPolicy::ERROR;
+    let mut model = Model::DMG;
+
+    while let Some(option) = rom.clone().filter(|argument| argument.starts_with("--")) {
+        let value = arg_iter
+            .next()
+            .unwrap_or_else(|| panic!("Cannot use {} without a value", option));
+
+        match option.as_str() {
+            "--lockup" => {
+                lockup_policy =
+                    parse_lockup_policy(&value).unwrap_or_else(|error| panic!("{}", error))
+            }
+            "--model" => model = parse_model(&value).unwrap_or_else(|error| panic!("{}", error)),
+            _ => panic!("Unknown option {}", option),
+        }
+
+        rom = arg_iter.next();
+    }
+
     // TODO: Hande loading a boot rom
 
     let rom_buffer = if let Some(rom_file) = rom {
@@ -19,14 +119,166 @@ fn main() {
         panic!("Cannot run emulator without a rom");
     };
 
-    let cpu = CPU::new(None, rom_buffer);
+    let mut cpu = CPU::new(None, rom_buffer);
+    cpu.skip_boot(model);
+    cpu.lockup_policy = lockup_policy;
     run(cpu);
 }
 
 fn run(mut cpu: CPU) {
     cpu.memory.verify_logo();
+
+    loop {
+        if let Err(lockup) = cpu.step() {
+            eprintln!("{}", lockup);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Runs the blargg test rom at `rom_file` for at most `seconds` of emulated time, prints its serial output and returns
+/// the exit code for its outcome.
+fn run_test_rom(rom_file: &str, seconds: u64) -> i32 {
+    let mut cpu = match test_rom::load(std::path::Path::new(rom_file)) {
+        Ok(cpu) => cpu,
+        Err(error) => {
+            eprintln!("{}", error);
+            return test_rom::Outcome::FAILED.exit_code();
+        }
+    };
+
+    let outcome = test_rom::run(&mut cpu, seconds * test_rom::CYCLES_PER_SECOND);
+    println!("{}", String::from_utf8_lossy(&cpu.memory.serial_output));
+
+    if outcome == test_rom::Outcome::TIMEOUT {
+        eprintln!("Timed out after {} seconds", seconds);
+    }
+
+    outcome.exit_code()
+}
+
+fn parse_lockup_policy(name: &str) -> Result<LockupPolicy, String> {
+    match name {
+        "freeze" => Ok(LockupPolicy::FREEZE),
+        "error" => Ok(LockupPolicy::ERROR),
+        "debugger" => Ok(LockupPolicy::DEBUGGER(print_lockup)),
+        _ => Err(format!(
+            "Unknown lockup policy {}, expected freeze, error or debugger",
+            name
+        )),
+    }
+}
+
+fn parse_model(name: &str) -> Result<Model, String> {
+    match name {
+        "dmg0" => Ok(Model::DMG0),
+        "dmg" => Ok(Model::DMG),
+        "mgb" => Ok(Model::MGB),
+        "sgb" => Ok(Model::SGB),
+        "sgb2" => Ok(Model::SGB2),
+        "cgb" => Ok(Model::CGB),
+        "agb" => Ok(Model::AGB),
+        _ => Err(format!(
+            "Unknown model {}, expected dmg0, dmg, mgb, sgb, sgb2, cgb or agb",
+            name
+        )),
+    }
+}
+
+/// The `--lockup debugger` hook, prints the lockup along with the registers and the instructions from the illegal
+/// opcode on, then exits.
+fn print_lockup(cpu: &CPU, lockup: &CPULockup) {
+    eprintln!("{}", lockup);
+    eprintln!("{}", doctor::trace_line(cpu));
+
+    for disassembly in disassembler::disassemble(&cpu.memory, lockup.pc, 8) {
+        eprintln!("{}", disassembly);
+    }
+
+    std::process::exit(1);
+}
+
+/// Parses the timeout in seconds of emulated time that optionally ends the arguments of the headless test modes.
+fn timeout_seconds(argument: Option<String>, default_seconds: u64) -> u64 {
+    argument
+        .map(|seconds| {
+            seconds
+                .parse()
+                .expect("Cannot parse the timeout in seconds")
+        })
+        .unwrap_or(default_seconds)
 }
 
 fn buffer_from_file(path: &str) -> Vec<u8> {
-    std::fs::read(path).expect(format!("Cannot read file at path: {}", path).as_str())
+    std::fs::read(path).unwrap_or_else(|_| panic!("Cannot read file at path: {}", path))
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use crate::test_rom::test_support::rom_sending;
+
+    // Writes `rom` to a file in the temporary directory and runs it with `--test-rom`
+    fn run_test_rom_file(name: &str, rom: &[u8]) -> i32 {
+        let path = std::env::temp_dir().join(format!("{}-{}.gb", name, std::process::id()));
+        std::fs::write(&path, rom).unwrap();
+
+        let exit_code = run_test_rom(path.to_str().unwrap(), 1);
+        std::fs::remove_file(&path).unwrap();
+
+        exit_code
+    }
+
+    #[test]
+    fn timeout_defaults_without_argument() {
+        assert_eq!(timeout_seconds(Some("5".to_string()), 60), 5);
+        assert_eq!(timeout_seconds(None, 60), 60);
+    }
+
+    #[test]
+    fn parses_lockup_policies() {
+        assert!(matches!(
+            parse_lockup_policy("freeze"),
+            Ok(LockupPolicy::FREEZE)
+        ));
+        assert!(matches!(
+            parse_lockup_policy("error"),
+            Ok(LockupPolicy::ERROR)
+        ));
+        assert!(matches!(
+            parse_lockup_policy("debugger"),
+            Ok(LockupPolicy::DEBUGGER(_))
+        ));
+        assert_eq!(
+            parse_lockup_policy("halt").err(),
+            Some("Unknown lockup policy halt, expected freeze, error or debugger".to_string())
+        );
+    }
+
+    #[test]
+    fn parses_models() {
+        assert_eq!(parse_model("dmg0"), Ok(Model::DMG0));
+        assert_eq!(parse_model("sgb2"), Ok(Model::SGB2));
+        assert_eq!(parse_model("agb"), Ok(Model::AGB));
+        assert_eq!(
+            parse_model("gba"),
+            Err("Unknown model gba, expected dmg0, dmg, mgb, sgb, sgb2, cgb or agb".to_string())
+        );
+    }
+
+    #[test]
+    fn test_rom_passes() {
+        assert_eq!(run_test_rom_file("passed", &rom_sending("Passed")), 0);
+    }
+
+    #[test]
+    fn test_rom_with_unsupported_size_fails() {
+        // A 64 KiB rom that has no memory bank controller according to its header
+        assert_eq!(run_test_rom_file("mbc1", &vec![0; 0x10000]), 1);
+    }
+
+    #[test]
+    fn test_rom_that_cannot_be_read_fails() {
+        assert_eq!(run_test_rom("/nonexistent/cpu_instrs.gb", 1), 1);
+    }
 }
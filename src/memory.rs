@@ -1,3 +1,6 @@
+use crate::bus::Bus;
+use crate::model::Model;
+
 pub const BANK_0_START: usize = 0x0000;
 pub const BANK_0_END: usize = 0x3FFF;
 pub const BANK_0_SIZE: usize = BANK_0_END - BANK_0_START + 1;
@@ -42,8 +45,27 @@ pub const HRAM_START: usize = 0xFF80;
 pub const HRAM_END: usize = 0xFFEE;
 pub const HRAM_SIZE: usize = HRAM_END - HRAM_START + 1;
 
+pub const CARTRIDGE_TYPE: usize = 0x0147;
+pub const HEADER_CHECKSUM: usize = 0x014D;
+
+pub const SERIAL_DATA: usize = 0xFF01;
+pub const SERIAL_CONTROL: usize = 0xFF02;
+pub const DIVIDER: usize = 0xFF04;
+pub const TIMER_COUNTER: usize = 0xFF05;
+pub const TIMER_MODULO: usize = 0xFF06;
+pub const TIMER_CONTROL: usize = 0xFF07;
+pub const INTERRUPT_FLAG: usize = 0xFF0F;
+pub const LCD_Y_COORDINATE: usize = 0xFF44;
+pub const SPEED_SWITCH: usize = 0xFF4D;
 pub const INTERRUPT_ENABLE: usize = 0xFFFF;
 
+const VBLANK_BYTE_POSITION: u8 = 0;
+const STAT_BYTE_POSITION: u8 = 1;
+const TIMER_BYTE_POSITION: u8 = 2;
+const SERIAL_BYTE_POSITION: u8 = 3;
+const JOYPAD_BYTE_POSITION: u8 = 4;
+
+#[derive(Clone, Copy)]
 pub struct InterruptFlags {
     pub vblank: bool,
     pub stat: bool,
@@ -64,41 +86,296 @@ impl InterruptFlags {
     }
 }
 
+impl std::convert::From<InterruptFlags> for u8 {
+    fn from(flags: InterruptFlags) -> Self {
+        (if flags.vblank { 1 } else { 0 }) << VBLANK_BYTE_POSITION
+            | (if flags.stat { 1 } else { 0 }) << STAT_BYTE_POSITION
+            | (if flags.timer { 1 } else { 0 }) << TIMER_BYTE_POSITION
+            | (if flags.serial { 1 } else { 0 }) << SERIAL_BYTE_POSITION
+            | (if flags.joypad { 1 } else { 0 }) << JOYPAD_BYTE_POSITION
+    }
+}
+
+impl std::convert::From<u8> for InterruptFlags {
+    fn from(value: u8) -> Self {
+        InterruptFlags {
+            vblank: ((value >> VBLANK_BYTE_POSITION) & 0b1) != 0,
+            stat: ((value >> STAT_BYTE_POSITION) & 0b1) != 0,
+            timer: ((value >> TIMER_BYTE_POSITION) & 0b1) != 0,
+            serial: ((value >> SERIAL_BYTE_POSITION) & 0b1) != 0,
+            joypad: ((value >> JOYPAD_BYTE_POSITION) & 0b1) != 0,
+        }
+    }
+}
+
+/// The memory bank controller of a cartridge, which maps the banks of a rom bigger than 32 KiB into the address space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cartridge {
+    // 32 KiB of rom without a memory bank controller
+    ROMONLY,
+    // Up to 2 MiB of rom. Its external RAM is always enabled and only the first RAM bank is used.
+    MBC1,
+}
+
+impl Cartridge {
+    /// Reads the cartridge type from the header of `rom`, returning an error for cartridges that aren't supported.
+    pub fn from_rom(rom: &[u8]) -> Result<Self, String> {
+        let size = rom.len();
+
+        if !(BANK_0_SIZE + BANK_N_SIZE..=0x200000).contains(&size) || !size.is_power_of_two() {
+            return Err(format!("unsupported rom size of {} bytes", size));
+        }
+
+        match rom[CARTRIDGE_TYPE] {
+            0x00 if size == BANK_0_SIZE + BANK_N_SIZE => Ok(Cartridge::ROMONLY),
+            0x00 => Err(format!(
+                "unsupported rom size of {} bytes without a memory bank controller",
+                size
+            )),
+            0x01..=0x03 => Ok(Cartridge::MBC1),
+            cartridge_type => Err(format!(
+                "unsupported cartridge type 0x{:02X}",
+                cartridge_type
+            )),
+        }
+    }
+}
+
 pub struct Memory {
-    pub bus: [u8; 0xFFFF],
+    pub bus: [u8; 0x10000],
+    // The whole rom, the banks that are mapped in are copied into `bus`
+    rom: Vec<u8>,
+    pub cartridge: Cartridge,
+    // The MBC1 registers: the lower 5 bits of the rom bank, the 2 bits above them and the banking mode
+    bank_low: u8,
+    bank_high: u8,
+    banking_mode: bool,
+    // LY reads this value while it is set. There is no PPU yet, without it LY never changes.
+    pub stub_lcd_y: Option<u8>,
+    // Internal 16 bit counter, DIV (0xFF04) is its upper byte
+    pub divider: u16,
+    // Whether CGB only registers such as KEY1 (0xFF4D) are available
+    pub cgb: bool,
+    pub double_speed: bool,
+    // KEY1 bit 0, the next STOP switches speed when set
+    pub speed_switch_armed: bool,
+    // IF (0xFF0F)
     pub interrupt_flags: InterruptFlags,
+    // IE (0xFFFF) is a plain 8 bit register, only its lower 5 bits select interrupts
+    pub interrupt_enable: u8,
+    // Every byte sent through the serial port
+    pub serial_output: Vec<u8>,
 }
 
 impl Memory {
-    pub fn new(boot_rom: Option<Vec<u8>>, rom: Vec<u8>) -> Self {
-        let rom_size = rom.as_slice().len();
-        let mut bus: [u8; 0xFFFF] = [0xFF; 0xFFFF];
-
-        bus[0x0000..(BANK_0_SIZE + BANK_N_SIZE)].copy_from_slice(
-            rom.as_slice().try_into().expect(
-                format!(
-                    "Rom size {} bigger than allowed rom size of {}",
-                    rom_size,
-                    (BANK_0_SIZE + BANK_N_SIZE)
-                )
-                .as_str(),
-            ),
-        );
+    pub fn new(_boot_rom: Option<Vec<u8>>, rom: Vec<u8>) -> Self {
+        let cartridge = Cartridge::from_rom(&rom).unwrap_or_else(|error| panic!("{}", error));
 
-        Memory {
-            bus,
+        let mut memory = Memory {
+            bus: [0xFF; 0x10000],
+            rom,
+            cartridge,
+            bank_low: 1,
+            bank_high: 0,
+            banking_mode: false,
+            stub_lcd_y: None,
+            divider: 0,
+            cgb: false,
+            double_speed: false,
+            speed_switch_armed: false,
             interrupt_flags: InterruptFlags::new(),
-        }
+            interrupt_enable: 0,
+            serial_output: Vec::new(),
+        };
+        memory.map_rom_banks();
+
+        memory
     }
 
     pub fn read_byte(&self, address: u16) -> u8 {
-        self.bus[address as usize]
+        match address as usize {
+            // The unused upper 3 bits of IF always read as 1
+            INTERRUPT_FLAG => u8::from(self.interrupt_flags) | 0xE0,
+            INTERRUPT_ENABLE => self.interrupt_enable,
+            DIVIDER => (self.divider >> 8) as u8,
+            LCD_Y_COORDINATE => self.stub_lcd_y.unwrap_or(self.bus[LCD_Y_COORDINATE]),
+            SPEED_SWITCH if self.cgb => {
+                (if self.double_speed { 0x80 } else { 0 })
+                    | 0x7E
+                    | (if self.speed_switch_armed { 1 } else { 0 })
+            }
+            _ => self.bus[address as usize],
+        }
+    }
+
+    pub fn write_byte(&mut self, address: u16, value: u8) {
+        match address as usize {
+            INTERRUPT_FLAG => self.interrupt_flags = InterruptFlags::from(value),
+            INTERRUPT_ENABLE => self.interrupt_enable = value,
+            // Writing any value to DIV resets the whole counter, which is a falling edge for TIMA if its bit was set
+            DIVIDER => {
+                if self.timer_input(self.divider) {
+                    self.increment_timer();
+                }
+
+                self.divider = 0;
+            }
+            SPEED_SWITCH if self.cgb => self.speed_switch_armed = value & 0b1 != 0,
+            // Writes to rom go to the registers of the memory bank controller, the rom itself never changes
+            BANK_0_START..=BANK_N_END => self.write_bank_register(address, value),
+            // LY is read-only, only the PPU changes it
+            LCD_Y_COORDINATE => {}
+            // Setting the start and internal clock bits of SC starts a transfer. Nothing is connected to the link
+            // port, so the transfer completes immediately, shifting in 0xFF.
+            SERIAL_CONTROL if value & 0x81 == 0x81 => {
+                self.serial_output.push(self.bus[SERIAL_DATA]);
+                self.bus[SERIAL_DATA] = 0xFF;
+                self.bus[SERIAL_CONTROL] = value & 0x7F;
+                self.interrupt_flags.serial = true;
+            }
+            _ => self.bus[address as usize] = value,
+        }
+    }
+
+    /// Sets the IO registers to the values the boot rom of `model` leaves behind.
+    ///
+    /// Registers whose value is not documented for a model keep the value they have on the DMG.
+    pub fn skip_boot(&mut self, model: Model) {
+        const IO_REGISTERS: [(u16, u8); 38] = [
+            (0xFF00, 0xCF), // P1
+            (0xFF01, 0x00), // SB
+            (0xFF02, 0x7E), // SC
+            (0xFF05, 0x00), // TIMA
+            (0xFF06, 0x00), // TMA
+            (0xFF07, 0xF8), // TAC
+            (0xFF0F, 0xE1), // IF
+            (0xFF10, 0x80), // NR10
+            (0xFF11, 0xBF), // NR11
+            (0xFF12, 0xF3), // NR12
+            (0xFF13, 0xFF), // NR13
+            (0xFF14, 0xBF), // NR14
+            (0xFF16, 0x3F), // NR21
+            (0xFF17, 0x00), // NR22
+            (0xFF18, 0xFF), // NR23
+            (0xFF19, 0xBF), // NR24
+            (0xFF1A, 0x7F), // NR30
+            (0xFF1B, 0xFF), // NR31
+            (0xFF1C, 0x9F), // NR32
+            (0xFF1D, 0xFF), // NR33
+            (0xFF1E, 0xBF), // NR34
+            (0xFF20, 0xFF), // NR41
+            (0xFF21, 0x00), // NR42
+            (0xFF22, 0x00), // NR43
+            (0xFF23, 0xBF), // NR44
+            (0xFF24, 0x77), // NR50
+            (0xFF25, 0xF3), // NR51
+            (0xFF26, 0xF1), // NR52
+            (0xFF40, 0x91), // LCDC
+            (0xFF41, 0x85), // STAT
+            (0xFF42, 0x00), // SCY
+            (0xFF43, 0x00), // SCX
+            (0xFF45, 0x00), // LYC
+            (0xFF46, 0xFF), // DMA
+            (0xFF47, 0xFC), // BGP
+            (0xFF4A, 0x00), // WY
+            (0xFF4B, 0x00), // WX
+            (0xFFFF, 0x00), // IE
+        ];
+
+        for (address, value) in IO_REGISTERS {
+            self.write_byte(address, value);
+        }
+
+        // LY is read-only to the CPU
+        self.bus[LCD_Y_COORDINATE] = 0x00;
+
+        self.cgb = model.is_cgb();
+        self.double_speed = false;
+        self.speed_switch_armed = false;
+
+        match model {
+            Model::DMG0 => {
+                self.divider = 0x1800;
+                self.write_byte(0xFF41, 0x81);
+                self.bus[LCD_Y_COORDINATE] = 0x91;
+            }
+            Model::DMG | Model::MGB => self.divider = 0xAB00,
+            Model::SGB | Model::SGB2 => {
+                self.divider = 0;
+                self.write_byte(0xFF26, 0xF0);
+            }
+            Model::CGB | Model::AGB => {
+                self.divider = 0;
+                self.write_byte(0xFF02, 0x7F); // SC
+                self.write_byte(0xFF46, 0x00); // DMA
+                self.write_byte(0xFF4F, 0xFE); // VBK
+                self.write_byte(0xFF56, 0x3E); // RP
+                self.write_byte(0xFF70, 0xF8); // SVBK
+            }
+        }
+    }
+
+    // MBC1 ignores the RAM enable register at 0x0000-0x1FFF here, its RAM is always enabled
+    fn write_bank_register(&mut self, address: u16, value: u8) {
+        if self.cartridge != Cartridge::MBC1 {
+            return;
+        }
+
+        match address {
+            0x2000..=0x3FFF => self.bank_low = value & 0x1F,
+            0x4000..=0x5FFF => self.bank_high = value & 0b11,
+            0x6000..=0x7FFF => self.banking_mode = value & 0b1 != 0,
+            _ => return,
+        }
+
+        self.map_rom_banks();
+    }
+
+    // The rom banks mapped at 0x0000 and 0x4000. Selecting bank 0 in the lower 5 bits selects bank 1 instead, and the
+    // upper 2 bits only apply to 0x0000 in banking mode 1.
+    fn rom_banks(&self) -> (usize, usize) {
+        let bank_count = self.rom.len() / BANK_N_SIZE;
+        let high = (self.bank_high as usize) << 5;
+        let low = (self.bank_low as usize).max(1);
+        let bank_0 = if self.banking_mode { high } else { 0 };
+
+        (bank_0 % bank_count, (high | low) % bank_count)
+    }
+
+    fn map_rom_banks(&mut self) {
+        let (bank_0, bank_n) = self.rom_banks();
+
+        self.bus[BANK_0_START..=BANK_0_END]
+            .copy_from_slice(&self.rom[bank_0 * BANK_0_SIZE..(bank_0 + 1) * BANK_0_SIZE]);
+        self.bus[BANK_N_START..=BANK_N_END]
+            .copy_from_slice(&self.rom[bank_n * BANK_N_SIZE..(bank_n + 1) * BANK_N_SIZE]);
+    }
+
+    // TIMA counts the falling edges of the divider bit selected by TAC, while TAC enables the timer
+    fn timer_input(&self, divider: u16) -> bool {
+        const DIVIDER_BITS: [u16; 4] = [9, 3, 5, 7];
+        let control = self.bus[TIMER_CONTROL];
+
+        control & 0b100 != 0 && (divider >> DIVIDER_BITS[(control & 0b11) as usize]) & 0b1 != 0
+    }
+
+    // When TIMA overflows it is reloaded from TMA and the timer interrupt is requested. Hardware does this one M-cycle
+    // after the overflow, here it happens right away.
+    fn increment_timer(&mut self) {
+        let (value, overflowed) = self.bus[TIMER_COUNTER].overflowing_add(1);
+
+        if overflowed {
+            self.bus[TIMER_COUNTER] = self.bus[TIMER_MODULO];
+            self.interrupt_flags.timer = true;
+        } else {
+            self.bus[TIMER_COUNTER] = value;
+        }
     }
 
     pub fn read_byte_range(&self, range: std::ops::Range<u16>) -> Vec<u8> {
         let mut result = Vec::with_capacity((range.end - range.start) as usize);
 
-        for value in range.start..=range.end {
+        for value in range {
             result.push(self.read_byte(value));
         }
 
@@ -106,7 +383,7 @@ impl Memory {
     }
 
     pub fn verify_logo(&self) {
-        let logo_data = self.read_byte_range(0x0104..0x0133);
+        let logo_data = self.read_byte_range(0x0104..0x0134);
 
         let matches = self
             .official_logo()
@@ -127,3 +404,288 @@ impl Memory {
         ]
     }
 }
+
+impl Bus for Memory {
+    fn read(&self, address: u16) -> u8 {
+        self.read_byte(address)
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.write_byte(address, value);
+    }
+
+    fn tick(&mut self) {
+        let previous = self.divider;
+        self.divider = self.divider.wrapping_add(4);
+
+        if self.timer_input(previous) && !self.timer_input(self.divider) {
+            self.increment_timer();
+        }
+    }
+
+    fn rom_bank(&self, address: u16) -> u8 {
+        let (bank_0, bank_n) = self.rom_banks();
+
+        match address as usize {
+            BANK_0_START..=BANK_0_END => bank_0 as u8,
+            BANK_N_START..=BANK_N_END => bank_n as u8,
+            _ => 0,
+        }
+    }
+
+    // The switch is armed through bit 0 of KEY1, which is only available on the CGB
+    fn switch_speed(&mut self) -> bool {
+        if !self.cgb || !self.speed_switch_armed {
+            return false;
+        }
+
+        self.double_speed = !self.double_speed;
+        self.speed_switch_armed = false;
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    fn setup() -> Memory {
+        Memory::new(None, vec![0; BANK_0_SIZE + BANK_N_SIZE])
+    }
+
+    #[test]
+    fn interrupt_registers_are_mapped() {
+        let mut memory = setup();
+        memory.write_byte(INTERRUPT_ENABLE as u16, 0b0000_0101);
+        memory.write_byte(INTERRUPT_FLAG as u16, 0b1111_0010);
+
+        assert_eq!(memory.interrupt_enable, 0b0000_0101);
+        assert!(memory.interrupt_flags.stat);
+        assert!(memory.interrupt_flags.joypad);
+
+        assert_eq!(memory.read_byte(INTERRUPT_ENABLE as u16), 0b0000_0101);
+        assert_eq!(memory.read_byte(INTERRUPT_FLAG as u16), 0b1111_0010);
+
+        // The upper 3 bits of IE read back as written, unlike those of IF
+        memory.write_byte(INTERRUPT_ENABLE as u16, 0b1110_0100);
+        memory.write_byte(INTERRUPT_FLAG as u16, 0b0000_0100);
+
+        assert_eq!(memory.read_byte(INTERRUPT_ENABLE as u16), 0b1110_0100);
+        assert_eq!(memory.read_byte(INTERRUPT_FLAG as u16), 0b1110_0100);
+    }
+
+    #[test]
+    fn divider_counts_and_resets() {
+        let mut memory = setup();
+        for _ in 0..63 {
+            memory.tick();
+        }
+        assert_eq!(memory.read_byte(DIVIDER as u16), 0);
+
+        memory.tick();
+        assert_eq!(memory.read_byte(DIVIDER as u16), 1);
+
+        memory.write_byte(DIVIDER as u16, 0xAB);
+        assert_eq!(memory.read_byte(DIVIDER as u16), 0);
+        assert_eq!(memory.divider, 0);
+    }
+
+    #[test]
+    fn read_byte_range_excludes_the_end() {
+        let mut rom = vec![0; BANK_0_SIZE + BANK_N_SIZE];
+        rom[0x0200..0x0204].copy_from_slice(&[0x01, 0x02, 0x03, 0x04]);
+        let memory = Memory::new(None, rom);
+
+        assert_eq!(memory.read_byte_range(0x0200..0x0203), [0x01, 0x02, 0x03]);
+        assert!(memory.read_byte_range(0x0200..0x0200).is_empty());
+    }
+
+    #[test]
+    fn rom_and_ly_are_read_only() {
+        let mut rom = vec![0; BANK_0_SIZE + BANK_N_SIZE];
+        rom[0x2000] = 0x12;
+        rom[0x7FFF] = 0x34;
+        let mut memory = Memory::new(None, rom);
+
+        memory.write_byte(0x2000, 0x01);
+        memory.write_byte(0x7FFF, 0xFF);
+        memory.write_byte(LCD_Y_COORDINATE as u16, 0x45);
+
+        assert_eq!(memory.read_byte(0x2000), 0x12);
+        assert_eq!(memory.read_byte(0x7FFF), 0x34);
+        assert_eq!(memory.read_byte(LCD_Y_COORDINATE as u16), 0xFF);
+
+        // VRAM right after the rom is still writable
+        memory.write_byte(0x8000, 0x56);
+        assert_eq!(memory.read_byte(0x8000), 0x56);
+    }
+
+    #[test]
+    fn ly_reads_the_stub_while_it_is_set() {
+        let mut memory = setup();
+        memory.skip_boot(Model::DMG);
+        assert_eq!(memory.read_byte(LCD_Y_COORDINATE as u16), 0x00);
+
+        memory.stub_lcd_y = Some(0x90);
+        assert_eq!(memory.read_byte(LCD_Y_COORDINATE as u16), 0x90);
+    }
+
+    // A 128 KiB MBC1 rom where every byte of a bank is the number of the bank
+    fn mbc1_memory() -> Memory {
+        let mut rom: Vec<u8> = (0..8u8)
+            .flat_map(|bank| std::iter::repeat_n(bank, BANK_N_SIZE))
+            .collect();
+        rom[CARTRIDGE_TYPE] = 0x01;
+
+        Memory::new(None, rom)
+    }
+
+    #[test]
+    fn mbc1_switches_rom_banks() {
+        let mut memory = mbc1_memory();
+        assert_eq!(memory.cartridge, Cartridge::MBC1);
+        assert_eq!(memory.read_byte(0x4000), 1);
+
+        memory.write_byte(0x2000, 0x05);
+        assert_eq!(memory.read_byte(0x4000), 5);
+        assert_eq!(memory.read_byte(0x7FFF), 5);
+        assert_eq!(memory.rom_bank(0x4000), 5);
+        assert_eq!(memory.read_byte(0x0000), 0);
+
+        // Bank 0 can't be selected in the switchable bank
+        memory.write_byte(0x3FFF, 0x00);
+        assert_eq!(memory.read_byte(0x4000), 1);
+
+        // The bank number wraps around the size of the rom
+        memory.write_byte(0x2000, 0x0B);
+        assert_eq!(memory.read_byte(0x4000), 3);
+    }
+
+    #[test]
+    fn mbc1_upper_bank_bits() {
+        let mut rom = vec![0; 0x100000];
+        rom[CARTRIDGE_TYPE] = 0x01;
+        rom[0x21 * BANK_N_SIZE] = 0x21;
+        rom[0x20 * BANK_N_SIZE] = 0x20;
+        let mut memory = Memory::new(None, rom);
+
+        memory.write_byte(0x4000, 0x01);
+        assert_eq!(memory.read_byte(0x4000), 0x21);
+        assert_eq!(memory.read_byte(0x0000), 0x00);
+
+        // Banking mode 1 applies the upper bits to 0x0000 as well
+        memory.write_byte(0x6000, 0x01);
+        assert_eq!(memory.read_byte(0x0000), 0x20);
+        assert_eq!(memory.rom_bank(0x0000), 0x20);
+    }
+
+    #[test]
+    fn cartridge_from_rom_rejects_unsupported_roms() {
+        let mut rom = vec![0; 0x10000];
+        assert_eq!(
+            Cartridge::from_rom(&rom),
+            Err("unsupported rom size of 65536 bytes without a memory bank controller".to_string())
+        );
+
+        rom[CARTRIDGE_TYPE] = 0x13;
+        assert_eq!(
+            Cartridge::from_rom(&rom),
+            Err("unsupported cartridge type 0x13".to_string())
+        );
+
+        assert_eq!(
+            Cartridge::from_rom(&[0; 0x4000]),
+            Err("unsupported rom size of 16384 bytes".to_string())
+        );
+    }
+
+    #[test]
+    fn timer_counts_and_overflows() {
+        let mut memory = setup();
+        memory.write_byte(TIMER_MODULO as u16, 0xAB);
+        memory.write_byte(TIMER_COUNTER as u16, 0xFE);
+
+        // Disabled, nothing is counted
+        memory.write_byte(TIMER_CONTROL as u16, 0b001);
+        for _ in 0..4 {
+            memory.tick();
+        }
+        assert_eq!(memory.read_byte(TIMER_COUNTER as u16), 0xFE);
+
+        // Enabled at a 16 T-cycle period
+        memory.write_byte(TIMER_CONTROL as u16, 0b101);
+        for _ in 0..4 {
+            memory.tick();
+        }
+        assert_eq!(memory.read_byte(TIMER_COUNTER as u16), 0xFF);
+        assert!(!memory.interrupt_flags.timer);
+
+        for _ in 0..4 {
+            memory.tick();
+        }
+        assert_eq!(memory.read_byte(TIMER_COUNTER as u16), 0xAB);
+        assert!(memory.interrupt_flags.timer);
+    }
+
+    #[test]
+    fn divider_reset_increments_timer_on_falling_edge() {
+        let mut memory = setup();
+        memory.write_byte(TIMER_CONTROL as u16, 0b101);
+        memory.write_byte(TIMER_COUNTER as u16, 0x10);
+        memory.divider = 0b1000;
+
+        memory.write_byte(DIVIDER as u16, 0);
+        assert_eq!(memory.read_byte(TIMER_COUNTER as u16), 0x11);
+    }
+
+    #[test]
+    fn serial_transfer_captures_output() {
+        let mut memory = setup();
+        memory.write_byte(SERIAL_DATA as u16, b'O');
+        memory.write_byte(SERIAL_CONTROL as u16, 0x80);
+        assert!(memory.serial_output.is_empty());
+
+        memory.write_byte(SERIAL_CONTROL as u16, 0x81);
+        memory.write_byte(SERIAL_DATA as u16, b'K');
+        memory.write_byte(SERIAL_CONTROL as u16, 0x81);
+
+        assert_eq!(memory.serial_output, b"OK");
+        assert_eq!(memory.read_byte(SERIAL_DATA as u16), 0xFF);
+        assert_eq!(memory.read_byte(SERIAL_CONTROL as u16), 0x01);
+        assert!(memory.interrupt_flags.serial);
+    }
+
+    #[test]
+    fn speed_switch_only_on_cgb() {
+        let mut memory = setup();
+        memory.write_byte(SPEED_SWITCH as u16, 0x01);
+        assert!(!memory.speed_switch_armed);
+
+        memory.cgb = true;
+        memory.write_byte(SPEED_SWITCH as u16, 0x01);
+        assert!(memory.speed_switch_armed);
+        assert_eq!(memory.read_byte(SPEED_SWITCH as u16), 0x7F);
+    }
+
+    #[test]
+    fn skip_boot_sets_io_registers() {
+        let mut memory = setup();
+        memory.skip_boot(Model::DMG);
+
+        assert_eq!(memory.read_byte(0xFF40), 0x91);
+        assert_eq!(memory.read_byte(0xFF47), 0xFC);
+        assert_eq!(memory.read_byte(DIVIDER as u16), 0xAB);
+        assert_eq!(memory.read_byte(INTERRUPT_FLAG as u16), 0xE1);
+        assert_eq!(memory.read_byte(INTERRUPT_ENABLE as u16), 0x00);
+        assert_eq!(memory.read_byte(SPEED_SWITCH as u16), 0xFF);
+        assert!(!memory.cgb);
+
+        memory.skip_boot(Model::CGB);
+
+        assert_eq!(memory.read_byte(0xFF02), 0x7F);
+        assert_eq!(memory.read_byte(0xFF70), 0xF8);
+        assert_eq!(memory.read_byte(SPEED_SWITCH as u16), 0x7E);
+        assert!(memory.cgb);
+    }
+}
@@ -0,0 +1,25 @@
+/// The Game Boy hardware models, each one's boot rom leaves the CPU and IO registers in a different state.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Model {
+    // Early original Game Boy with the first revision of the boot rom
+    DMG0,
+    // Original Game Boy
+    DMG,
+    // Game Boy Pocket
+    MGB,
+    // Super Game Boy
+    SGB,
+    // Super Game Boy 2
+    SGB2,
+    // Game Boy Color
+    CGB,
+    // Game Boy Advance running a Game Boy Color game
+    AGB,
+}
+
+impl Model {
+    /// Whether the model has the Game Boy Color hardware, such as the CGB only registers.
+    pub fn is_cgb(self) -> bool {
+        matches!(self, Model::CGB | Model::AGB)
+    }
+}
@@ -0,0 +1,284 @@
+use crate::cpu::{CPULockup, CPUState, LockupPolicy, CPU};
+use crate::test_rom::{self, Outcome};
+use std::path::{Path, PathBuf};
+
+// LD B, B does nothing on hardware, the Mooneye tests execute it as a software breakpoint once they are done
+const LD_B_B: u8 = 0x40;
+
+// The values in B, C, D, E, H and L at the breakpoint when a test passed
+const FIBONACCI: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+/// The result of running one test rom out of a directory.
+pub struct Report {
+    pub path: PathBuf,
+    // The reason the rom could not be run if it wasn't
+    pub outcome: Result<Outcome, String>,
+    // Where the CPU locked up if that is why the rom failed
+    pub lockup: Option<CPULockup>,
+}
+
+/// Runs a Mooneye test rom until it executes the `LD B, B` breakpoint, or until `timeout_cycles` T-cycles have
+/// passed.
+///
+/// Returns an error if the CPU locked up before reaching the breakpoint, which means the test failed.
+pub fn run(cpu: &mut CPU, timeout_cycles: u64) -> Result<Outcome, CPULockup> {
+    cpu.lockup_policy = LockupPolicy::ERROR;
+
+    let mut elapsed_cycles = 0;
+
+    while elapsed_cycles < timeout_cycles {
+        if cpu.state == CPUState::RUNNING && cpu.memory.read_byte(cpu.pc) == LD_B_B {
+            let registers = &cpu.registers;
+            let values = [
+                registers.b,
+                registers.c,
+                registers.d,
+                registers.e,
+                registers.h,
+                registers.l,
+            ];
+
+            return if values == FIBONACCI {
+                Ok(Outcome::PASSED)
+            } else {
+                Ok(Outcome::FAILED)
+            };
+        }
+
+        elapsed_cycles += cpu.step()? as u64;
+    }
+
+    Ok(Outcome::TIMEOUT)
+}
+
+/// Runs every `.gb` rom in `directory` and its subdirectories, in alphabetical order.
+///
+/// A rom that can't be read or isn't supported is reported as skipped, only failing to list `directory` is an error.
+pub fn run_directory(directory: &Path, timeout_cycles: u64) -> std::io::Result<Vec<Report>> {
+    let mut roms = Vec::new();
+    find_roms(directory, &mut roms)?;
+    roms.sort();
+
+    let mut reports = Vec::with_capacity(roms.len());
+
+    for path in roms {
+        let (outcome, lockup) = match test_rom::load(&path) {
+            Ok(mut cpu) => match run(&mut cpu, timeout_cycles) {
+                Ok(outcome) => (Ok(outcome), None),
+                Err(lockup) => (Ok(Outcome::FAILED), Some(lockup)),
+            },
+            Err(reason) => (Err(reason), None),
+        };
+
+        reports.push(Report {
+            path,
+            outcome,
+            lockup,
+        });
+    }
+
+    Ok(reports)
+}
+
+/// Whether every rom in `reports` ran and passed. Roms that were skipped count as failures, so a directory of roms
+/// that can't be run doesn't pass.
+pub fn all_passed(reports: &[Report]) -> bool {
+    !reports.is_empty()
+        && reports
+            .iter()
+            .all(|report| report.outcome == Ok(Outcome::PASSED))
+}
+
+/// Prints a table with the result of every rom, with paths relative to `directory`, followed by a summary.
+pub fn print_summary(directory: &Path, reports: &[Report]) {
+    let names: Vec<String> = reports
+        .iter()
+        .map(|report| {
+            let path = report.path.strip_prefix(directory).unwrap_or(&report.path);
+            path.display().to_string()
+        })
+        .collect();
+    let width = names.iter().map(String::len).max().unwrap_or(0).max(3);
+
+    println!("{:<width$}  RESULT", "ROM", width = width);
+
+    for (name, report) in names.iter().zip(reports) {
+        let result = match (&report.outcome, &report.lockup) {
+            (Ok(outcome), Some(lockup)) => format!("{:?} ({})", outcome, lockup),
+            (Ok(outcome), None) => format!("{:?}", outcome),
+            (Err(reason), _) => format!("SKIPPED ({})", reason),
+        };
+
+        println!("{:<width$}  {}", name, result, width = width);
+    }
+
+    let count = |outcome: Outcome| {
+        reports
+            .iter()
+            .filter(|report| report.outcome == Ok(outcome))
+            .count()
+    };
+    let skipped = reports
+        .iter()
+        .filter(|report| report.outcome.is_err())
+        .count();
+
+    println!(
+        "\n{} passed, {} failed, {} timed out, {} skipped",
+        count(Outcome::PASSED),
+        count(Outcome::FAILED),
+        count(Outcome::TIMEOUT),
+        skipped
+    );
+}
+
+fn find_roms(directory: &Path, roms: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            find_roms(&path, roms)?;
+        } else if path.extension().is_some_and(|extension| extension == "gb") {
+            roms.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use crate::test_rom::rom_with_program;
+    use crate::test_rom::test_support::cpu_with_program;
+    use crate::test_rom::CYCLES_PER_SECOND;
+
+    // Loads `values` into B, C, D, E, H and L, executes the breakpoint and loops forever. Addresses are relative to
+    // the start of the program.
+    //
+    // 0000: LD B, values[0]
+    // 0002: LD C, values[1]
+    // 0004: LD D, values[2]
+    // 0006: LD E, values[3]
+    // 0008: LD H, values[4]
+    // 000A: LD L, values[5]
+    // 000C: LD B, B
+    // 000D: JR 0x000D
+    fn program(values: [u8; 6]) -> Vec<u8> {
+        vec![
+            0x06, values[0], 0x0E, values[1], 0x16, values[2], 0x1E, values[3], 0x26, values[4],
+            0x2E, values[5], LD_B_B, 0x18, 0xFE,
+        ]
+    }
+
+    fn rom(values: [u8; 6]) -> Vec<u8> {
+        rom_with_program(&program(values))
+    }
+
+    fn run_rom(rom: Vec<u8>) -> Result<Outcome, CPULockup> {
+        let mut cpu = test_rom::from_rom(rom).unwrap();
+
+        run(&mut cpu, CYCLES_PER_SECOND)
+    }
+
+    #[test]
+    fn passes_with_fibonacci_registers() {
+        assert_eq!(run_rom(rom(FIBONACCI)), Ok(Outcome::PASSED));
+    }
+
+    #[test]
+    fn fails_with_other_registers() {
+        assert_eq!(run_rom(rom([0x42; 6])), Ok(Outcome::FAILED));
+    }
+
+    #[test]
+    fn passes_after_waiting_for_vblank() {
+        // Polls LY until vblank like `wait_vblank` in the Mooneye test roms, before the breakpoint
+        //
+        // 0100: LDH A, (0x44)
+        // 0102: CP 0x90
+        // 0104: JR C, 0x0100
+        let mut wait_vblank = vec![0xF0, 0x44, 0xFE, 0x90, 0x38, 0xFA];
+        wait_vblank.extend(program(FIBONACCI));
+        let mut cpu = cpu_with_program(&wait_vblank);
+
+        assert_eq!(run(&mut cpu, CYCLES_PER_SECOND), Ok(Outcome::PASSED));
+    }
+
+    #[test]
+    fn reports_where_the_cpu_locked_up() {
+        let mut rom = rom(FIBONACCI);
+        rom[0x0100] = 0xD3;
+
+        let lockup = run_rom(rom).unwrap_err();
+
+        assert_eq!(lockup.opcode, 0xD3);
+        assert_eq!(lockup.bank, 0);
+    }
+
+    #[test]
+    fn times_out_without_breakpoint() {
+        let mut rom = rom(FIBONACCI);
+        rom[0x010C] = 0x00;
+
+        assert_eq!(run_rom(rom), Ok(Outcome::TIMEOUT));
+    }
+
+    #[test]
+    fn runs_every_rom_in_a_directory() {
+        let directory = std::env::temp_dir().join(format!("mooneye-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("bits")).unwrap();
+        std::fs::write(directory.join("pass.gb"), rom(FIBONACCI)).unwrap();
+        std::fs::write(directory.join("bits/fail.gb"), rom([0x42; 6])).unwrap();
+        std::fs::write(directory.join("mbc1.gb"), vec![0; 0x10000]).unwrap();
+        std::fs::write(directory.join("notes.txt"), "").unwrap();
+
+        let mut lockup = rom(FIBONACCI);
+        lockup[0x0100] = 0xD3;
+        std::fs::write(directory.join("lockup.gb"), lockup).unwrap();
+
+        // A link to a rom that doesn't exist can't be read
+        std::os::unix::fs::symlink(directory.join("missing.gb"), directory.join("broken.gb"))
+            .unwrap();
+
+        let reports = run_directory(&directory, CYCLES_PER_SECOND).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        let names: Vec<_> = reports
+            .iter()
+            .map(|report| report.path.strip_prefix(&directory).unwrap())
+            .collect();
+
+        assert_eq!(
+            names,
+            [
+                "bits/fail.gb",
+                "broken.gb",
+                "lockup.gb",
+                "mbc1.gb",
+                "pass.gb"
+            ]
+            .map(Path::new)
+        );
+
+        assert_eq!(reports[0].outcome, Ok(Outcome::FAILED));
+        assert!(reports[0].lockup.is_none());
+
+        let reason = reports[1].outcome.as_ref().unwrap_err();
+        assert!(reason.starts_with("Cannot read"), "{}", reason);
+
+        assert_eq!(reports[2].outcome, Ok(Outcome::FAILED));
+        assert_eq!(reports[2].lockup.map(|lockup| lockup.opcode), Some(0xD3));
+
+        assert_eq!(
+            reports[3].outcome,
+            Err("unsupported rom size of 65536 bytes without a memory bank controller".to_string())
+        );
+        assert_eq!(reports[4].outcome, Ok(Outcome::PASSED));
+
+        assert!(!all_passed(&reports));
+        assert!(all_passed(&reports[4..]));
+        assert!(!all_passed(&reports[3..4]));
+        assert!(!all_passed(&[]));
+    }
+}
@@ -0,0 +1,215 @@
+use crate::cpu::{LockupPolicy, CPU};
+use crate::memory::Cartridge;
+use crate::model::Model;
+use std::convert::Infallible;
+use std::path::Path;
+
+// T-cycles per second of emulated time on the DMG
+pub const CYCLES_PER_SECOND: u64 = 4_194_304;
+
+/// How a test rom run ended.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    // The test rom reported success
+    PASSED,
+    // The test rom reported a failure, or the CPU locked up
+    FAILED,
+    // The test rom didn't report a result in time
+    TIMEOUT,
+}
+
+impl Outcome {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Outcome::PASSED => 0,
+            Outcome::FAILED => 1,
+            Outcome::TIMEOUT => 2,
+        }
+    }
+}
+
+/// Reads the test rom at `path` and puts it in the state the DMG boot rom leaves behind.
+///
+/// Only roms without a memory bank controller or with MBC1 are supported, any other rom is reported as an error.
+pub fn load(path: &Path) -> Result<CPU, String> {
+    let rom = std::fs::read(path)
+        .map_err(|error| format!("Cannot read {}: {}", path.display(), error))?;
+
+    from_rom(rom)
+}
+
+/// Same as `load`, for a rom that is already in memory.
+pub fn from_rom(rom: Vec<u8>) -> Result<CPU, String> {
+    Cartridge::from_rom(&rom)?;
+
+    let mut cpu = CPU::new(None, rom);
+    cpu.skip_boot(Model::DMG);
+
+    // There is no PPU yet, LY is stuck at the start of vblank so the roms don't wait for it forever
+    cpu.memory.stub_lcd_y = Some(0x90);
+
+    Ok(cpu)
+}
+
+/// Runs a blargg test rom headless until it reports its result through the serial port, or until `timeout_cycles`
+/// T-cycles have passed.
+///
+/// Everything the rom sends through the serial port is left in `cpu.memory.serial_output`.
+pub fn run(cpu: &mut CPU, timeout_cycles: u64) -> Outcome {
+    let result = run_with(cpu, timeout_cycles, |_| Ok::<(), Infallible>(()));
+
+    result.unwrap_or_else(|never| match never {})
+}
+
+/// Same as `run`, but calls `before_step` before every step. An error returned by `before_step` ends the run.
+pub fn run_with<E>(
+    cpu: &mut CPU,
+    timeout_cycles: u64,
+    mut before_step: impl FnMut(&CPU) -> Result<(), E>,
+) -> Result<Outcome, E> {
+    cpu.lockup_policy = LockupPolicy::ERROR;
+
+    let mut elapsed_cycles = 0;
+    let mut output_length = 0;
+
+    while elapsed_cycles < timeout_cycles {
+        before_step(cpu)?;
+
+        match cpu.step() {
+            Ok(cycles) => elapsed_cycles += cycles as u64,
+            Err(lockup) => {
+                eprintln!("{}", lockup);
+                return Ok(Outcome::FAILED);
+            }
+        }
+
+        // Only search the output again when something new was sent
+        let output = &cpu.memory.serial_output;
+        if output.len() != output_length {
+            output_length = output.len();
+
+            if contains(output, b"Passed") {
+                return Ok(Outcome::PASSED);
+            }
+
+            if contains(output, b"Failed") {
+                return Ok(Outcome::FAILED);
+            }
+        }
+    }
+
+    Ok(Outcome::TIMEOUT)
+}
+
+fn contains(output: &[u8], text: &[u8]) -> bool {
+    output.windows(text.len()).any(|window| window == text)
+}
+
+/// A 32 KiB rom without a memory bank controller that starts `program` at 0x0100.
+pub fn rom_with_program(program: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x0100..0x0100 + program.len()].copy_from_slice(program);
+
+    rom
+}
+
+/// Test roms for the headless modes, built around small programs.
+#[cfg(test)]
+pub mod test_support {
+    use super::*;
+
+    // Sends the zero terminated text at 0x0111 through the serial port, then loops forever
+    //
+    // 0100: LD HL, 0x0111
+    // 0103: LD A, (HL+)
+    // 0104: AND A
+    // 0105: JR Z, 0x010F
+    // 0107: LDH (0x01), A
+    // 0109: LD A, 0x81
+    // 010B: LDH (0x02), A
+    // 010D: JR 0x0103
+    // 010F: JR 0x010F
+    const SEND_TEXT: [u8; 17] = [
+        0x21, 0x11, 0x01, 0x2A, 0xA7, 0x28, 0x08, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0x18, 0xF4,
+        0x18, 0xFE,
+    ];
+
+    /// A rom that sends `text` through the serial port, like the blargg roms report their results.
+    pub fn rom_sending(text: &str) -> Vec<u8> {
+        let mut rom = rom_with_program(&SEND_TEXT);
+        rom[0x0111..0x0111 + text.len()].copy_from_slice(text.as_bytes());
+
+        rom
+    }
+
+    /// Loads `rom_with_program(program)` the way `load` loads a test rom.
+    pub fn cpu_with_program(program: &[u8]) -> CPU {
+        from_rom(rom_with_program(program)).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::test_support::*;
+    use super::*;
+    use crate::memory::{CARTRIDGE_TYPE, LCD_Y_COORDINATE};
+
+    fn setup(text: &str) -> CPU {
+        from_rom(rom_sending(text)).unwrap()
+    }
+
+    #[test]
+    fn passes_on_passed() {
+        let mut cpu = setup("cpu_instrs\n\nPassed all tests\n");
+
+        assert_eq!(run(&mut cpu, CYCLES_PER_SECOND), Outcome::PASSED);
+        assert_eq!(cpu.memory.serial_output, b"cpu_instrs\n\nPassed");
+    }
+
+    #[test]
+    fn fails_on_failed() {
+        let mut cpu = setup("01-special\n\nFailed #6\n");
+
+        assert_eq!(run(&mut cpu, CYCLES_PER_SECOND), Outcome::FAILED);
+    }
+
+    #[test]
+    fn times_out_without_result() {
+        let mut cpu = setup("01-special\n\n");
+
+        assert_eq!(run(&mut cpu, CYCLES_PER_SECOND), Outcome::TIMEOUT);
+        assert_eq!(cpu.memory.serial_output, b"01-special\n\n");
+    }
+
+    #[test]
+    fn fails_on_lockup() {
+        let mut cpu = setup("");
+        cpu.pc = 0x0000;
+        cpu.memory.bus[0x0000] = 0xD3;
+
+        assert_eq!(run(&mut cpu, CYCLES_PER_SECOND), Outcome::FAILED);
+    }
+
+    #[test]
+    fn loads_mbc1_roms_with_ly_at_vblank() {
+        // The size of the combined cpu_instrs.gb
+        let mut rom = vec![0; 0x10000];
+        rom[CARTRIDGE_TYPE] = 0x01;
+
+        let cpu = from_rom(rom).unwrap();
+
+        assert_eq!(cpu.memory.read_byte(LCD_Y_COORDINATE as u16), 0x90);
+        assert_eq!(cpu.pc, 0x0100);
+    }
+
+    #[test]
+    fn rejects_unsupported_roms() {
+        let mut rom = vec![0; 0x10000];
+        rom[CARTRIDGE_TYPE] = 0x19;
+
+        assert_eq!(
+            from_rom(rom).err(),
+            Some("unsupported cartridge type 0x19".to_string())
+        );
+    }
+}